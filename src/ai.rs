@@ -8,15 +8,18 @@
 use std::cmp::Ordering;
 
 use crate::book::{BookState, Formation};
-use crate::effect::EffectBoard;
-use crate::log::LoggerTrait;
+use crate::effect::{EffectBoard, EffectBoardCell};
+use crate::log::{LoggerTrait, NullLogger};
 use crate::my_move;
 use crate::position::MoveCmd;
 use crate::prelude::*;
 use crate::price::{PRICES_0, PRICES_1, PRICES_2, PRICES_3};
-use crate::record::RecordEntry;
+use crate::record::{Record, RecordEntry};
+#[cfg(feature = "save")]
+use crate::sfen;
 use crate::util::{self, WrappingAddExt, WrappingSubExt};
 use crate::your_move;
+use crate::{Error, Result};
 
 //--------------------------------------------------------------------
 // 玉の位置
@@ -26,6 +29,20 @@ pub fn find_king_sq(board: &Board, side: Side) -> Option<Sq> {
     Sq::iter_valid().find(|&sq| board[sq].is_side_pt(side, Piece::King))
 }
 
+/// 玉の隣接マスの利きの状態 cell について、side 側の玉の逃げ道が塞がれているかを返す。
+/// (相手の利き数) >= (side の利き数) なら塞がれているとみなす。
+pub fn is_choked(cell: &EffectBoardCell, side: Side) -> bool {
+    cell[side.inv()].count() >= cell[side].count()
+}
+
+//--------------------------------------------------------------------
+// 序盤処理の強制
+//--------------------------------------------------------------------
+
+/// 6 手目以前に your 側がこれらのマスへ指した場合、必ず序盤処理を行う。
+/// マスは my 側が先手の場合のもの。
+pub const OPENING_SPECIAL_DSTS: &[Sq] = &[Sq::from_xy(4, 5), Sq::from_xy(5, 4), Sq::from_xy(2, 8)];
+
 //--------------------------------------------------------------------
 // 候補手とその付随情報
 //--------------------------------------------------------------------
@@ -79,6 +96,7 @@ impl CandInfo {
 
 /// root 局面の評価
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct RootEval {
     pub adv_price: u8,    // 最大駒得マスの your 駒の価値
     pub disadv_price: u8, // 最大駒損マスの my 駒の価値
@@ -89,6 +107,7 @@ pub struct RootEval {
 
 /// 局面の評価
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct PositionEval {
     pub adv_price: u8,          // 最大駒得マスの your 駒の価値 (駒得マスがない場合は 0)
     pub adv_sq: Sq,             // 最大駒得マス (ない場合は SQ_INVALID)
@@ -108,6 +127,7 @@ pub struct PositionEval {
 /// 候補手の評価
 /// (*) の付いた項目は最善手との比較時にさまざまな基準により修正を受ける。
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct CandEval {
     pub adv_price: u8,        // 最大駒得マスの your 駒の価値             (*)
     pub capture_price: u8,    // 取れる your 駒の価値 (取れない場合は 0)  (*)
@@ -121,6 +141,7 @@ pub struct CandEval {
 
 /// 最善手の評価
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct BestEval {
     pub adv_price: u8,
     pub adv_sq: Sq,
@@ -160,6 +181,97 @@ impl Default for BestEval {
     }
 }
 
+/// 候補手評価の (*) 項目の差分 (wrapping)
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CandEvalDelta {
+    pub adv_price: i8,
+    pub capture_price: i8,
+    pub disadv_price: i8,
+    pub nega: i8,
+    pub posi: i8,
+}
+
+/// tweak_eval() の修正規則 1 つによる候補手評価の変化
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TweakStep {
+    pub rule: &'static str,
+    pub before: CandEval,
+    pub after: CandEval,
+}
+
+impl TweakStep {
+    pub fn delta(&self) -> CandEvalDelta {
+        let d = |after: u8, before: u8| after.wrapping_sub(before) as i8;
+        CandEvalDelta {
+            adv_price: d(self.after.adv_price, self.before.adv_price),
+            capture_price: d(self.after.capture_price, self.before.capture_price),
+            disadv_price: d(self.after.disadv_price, self.before.disadv_price),
+            nega: d(self.after.nega, self.before.nega),
+            posi: d(self.after.posi, self.before.posi),
+        }
+    }
+}
+
+/// tweak_eval() の各修正規則のうち、実際に評価値を変えたものの記録。
+/// rejected_by は候補手を却下した規則 (却下されなければ None)。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TweakTrace {
+    pub initial: CandEval,
+    pub steps: Vec<TweakStep>,
+    pub rejected_by: Option<&'static str>,
+}
+
+impl TweakTrace {
+    fn new(initial: CandEval) -> Self {
+        Self {
+            initial,
+            steps: Vec::new(),
+            rejected_by: None,
+        }
+    }
+
+    /// 直前の状態から変化していれば rule による修正として記録する。
+    fn record(&mut self, rule: &'static str, cand_eval: &CandEval) {
+        let before = self.current();
+        if before != cand_eval {
+            let before = before.clone();
+            self.steps.push(TweakStep {
+                rule,
+                before,
+                after: cand_eval.clone(),
+            });
+        }
+    }
+
+    /// 最後に記録された修正後の評価値 (修正がなければ初期値) を返す。
+    pub fn current(&self) -> &CandEval {
+        self.steps
+            .last()
+            .map(|step| &step.after)
+            .unwrap_or(&self.initial)
+    }
+}
+
+/// 思考時の候補手の制限 (think_limited(), think_with_filter() 用)。制限しなければ原作通り。
+#[derive(Default)]
+struct CandLimit<'a> {
+    max_cands: Option<usize>,                  // 評価する候補手数の上限
+    filter: Option<&'a dyn Fn(&Move) -> bool>, // 候補手とするかどうかの判定
+}
+
+impl CandLimit<'_> {
+    fn is_limited(&self) -> bool {
+        self.max_cands.is_some() || self.filter.is_some()
+    }
+
+    fn allows(&self, mv: &Move) -> bool {
+        match self.filter {
+            Some(filter) => filter(mv),
+            None => true,
+        }
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum TweakResult {
     Normal,
@@ -167,6 +279,82 @@ enum TweakResult {
     Reject,
 }
 
+//--------------------------------------------------------------------
+// 評価値の揺らぎ
+//
+// 強さ調整用。tweak_eval() で修正された候補手評価の (*) 項目に一様乱数を加える。
+// amount が 0 (デフォルト) なら何もしないので原作通りの動作となる。
+// amount が 0 でなければ指し手が原作と一致しなくなるため、エミュレータとの照合はできない。
+//--------------------------------------------------------------------
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct EvalJitter {
+    amount: u8,
+    state: u64, // splitmix64 の状態
+}
+
+impl EvalJitter {
+    fn new(amount: u8, seed: u64) -> Self {
+        Self {
+            amount,
+            state: seed,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// value に [-amount, amount] の一様乱数を加える (0..=255 に飽和)。
+    fn perturb(&mut self, value: u8) -> u8 {
+        let width = 2 * u64::from(self.amount) + 1;
+        let delta = (self.next_u64() % width) as i32 - i32::from(self.amount);
+        num_traits::clamp(i32::from(value) + delta, 0, 255) as u8
+    }
+
+    fn apply(&mut self, cand_eval: &mut CandEval) {
+        if self.amount == 0 {
+            return;
+        }
+        cand_eval.adv_price = self.perturb(cand_eval.adv_price);
+        cand_eval.capture_price = self.perturb(cand_eval.capture_price);
+        cand_eval.disadv_price = self.perturb(cand_eval.disadv_price);
+        cand_eval.nega = self.perturb(cand_eval.nega);
+        cand_eval.posi = self.perturb(cand_eval.posi);
+    }
+}
+
+//--------------------------------------------------------------------
+// 保存/復元用
+//
+// 局面は SFEN で、指し手は SFEN の指し手表記で保存する。
+// 形式を変更したら AI_SAVE_VERSION を上げること。
+//--------------------------------------------------------------------
+
+#[cfg(feature = "save")]
+const AI_SAVE_VERSION: u32 = 1;
+
+#[cfg(feature = "save")]
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct AiSaveData {
+    version: u32,
+    my: Side,
+    timelimit: bool,
+    sfen: String,
+    mv_your: Option<String>,
+    progress_ply: u8,
+    progress_level: u8,
+    progress_level_sub: u8,
+    book_state: BookState,
+    naitou_best_src: u8,
+    eval_jitter_amount: u8,
+    eval_jitter_state: u64,
+}
+
 //--------------------------------------------------------------------
 // 詰み判定
 //--------------------------------------------------------------------
@@ -197,6 +385,133 @@ fn naitou_drop_src(pt: Piece) -> u8 {
     }
 }
 
+//--------------------------------------------------------------------
+// 進行度レベル
+//--------------------------------------------------------------------
+
+/// 進行度レベル (0..=3)。
+///
+/// 0 は序盤 (定跡処理を行う)。序盤処理が終わるか、駒が 5 回ぶつかると 1 になる。
+/// 以後は your 指し手後の進行度 progress_ply に応じて 2, 3 と進む。
+/// 0 以外のレベルは駒価値が等しい駒同士の交換を駒得とみなすかどうかに影響する。
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
+pub struct ProgressLevel(u8);
+
+impl ProgressLevel {
+    pub const OPENING: Self = Self(0);
+    pub const MIDDLE: Self = Self(1);
+
+    pub fn new(value: u8) -> Self {
+        assert!(value <= 3);
+        Self(value)
+    }
+
+    pub fn get(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_opening(self) -> bool {
+        self.0 == 0
+    }
+
+    /// 序盤を終える。
+    pub fn end_opening(&mut self) {
+        self.0 = 1;
+    }
+
+    /// your 指し手後の更新。progress_ply は更新後の値を渡す。
+    ///
+    /// 51 以上なら 1 段階進め (最大 2)、71 以上なら 3 とする。
+    /// 原作では 51 以上のとき序盤でも 1 段階しか進まない。
+    pub fn advance_for_ply(&mut self, progress_ply: u8) {
+        if progress_ply >= 51 {
+            self.0 = std::cmp::min(2, self.0 + 1);
+        }
+        if progress_ply >= 71 {
+            self.0 = 3;
+        }
+    }
+
+    /// 駒がぶつかったときの更新。サブ進行度 level_sub を進め、5 に達したら序盤を終える。
+    /// 序盤以外では何もしない。
+    pub fn bump_on_exchange(&mut self, level_sub: &mut u8) {
+        if !self.is_opening() {
+            return;
+        }
+        *level_sub += 1;
+        if *level_sub >= 5 {
+            self.end_opening();
+        }
+    }
+}
+
+//--------------------------------------------------------------------
+// 戦力評価の手数補正
+//--------------------------------------------------------------------
+
+/// 戦力評価 (power) の手数補正値を返す。
+/// 77 手目以降かどうかで係数が変わる。
+///
+/// 時間制限の有無はこの値に影響しない(時間制限が影響するのは初期戦型のみ)。
+pub fn power_ply_factor(progress_ply: u8) -> u8 {
+    let factor = progress_ply / 11;
+    if factor >= 7 {
+        2 * factor
+    } else {
+        factor
+    }
+}
+
+//--------------------------------------------------------------------
+// 思考結果
+//--------------------------------------------------------------------
+
+/// 思考結果。RecordEntry と同じ情報を、指し手と終局の区別が明確な形で表す。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ThinkOutcome {
+    Move(Move), // 指し手 (対局は続く)
+    Win(Move),  // 指し手を指して勝ちを宣言する (RecordEntry::MyWin)
+    YourResign, // your 側の自殺手により your 側の負け (RecordEntry::YourSuicide)
+    YourWin,    // 思考ルーチンの投了 (RecordEntry::YourWin)
+}
+
+impl ThinkOutcome {
+    /// 指すべき手があれば返す。
+    pub fn mv(&self) -> Option<&Move> {
+        match self {
+            Self::Move(mv) | Self::Win(mv) => Some(mv),
+            Self::YourResign | Self::YourWin => None,
+        }
+    }
+
+    /// 終局したかどうかを返す。勝ち宣言の場合も終局とみなす。
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, Self::Move(_))
+    }
+}
+
+impl From<ThinkOutcome> for RecordEntry {
+    fn from(outcome: ThinkOutcome) -> Self {
+        match outcome {
+            ThinkOutcome::Move(mv) => Self::Move(mv),
+            ThinkOutcome::Win(mv) => Self::MyWin(mv),
+            ThinkOutcome::YourResign => Self::YourSuicide,
+            ThinkOutcome::YourWin => Self::YourWin,
+        }
+    }
+}
+
+impl From<RecordEntry> for ThinkOutcome {
+    fn from(entry: RecordEntry) -> Self {
+        match entry {
+            RecordEntry::Move(mv) => Self::Move(mv),
+            RecordEntry::MyWin(mv) => Self::Win(mv),
+            RecordEntry::YourSuicide => Self::YourResign,
+            RecordEntry::YourWin => Self::YourWin,
+        }
+    }
+}
+
 //--------------------------------------------------------------------
 // undo 用
 //--------------------------------------------------------------------
@@ -205,7 +520,7 @@ fn naitou_drop_src(pt: Piece) -> u8 {
 pub struct StepMyCmd {
     mv_cmd: Option<MoveCmd>,
     progress_ply: u8,
-    progress_level: u8,
+    progress_level: ProgressLevel,
     progress_level_sub: u8,
     book_state: BookState,
     naitou_best_src: u8,
@@ -216,7 +531,7 @@ pub struct MoveYourCmd {
     mv_cmd: MoveCmd,
     mv_your: Option<Move>,
     progress_ply: u8,
-    progress_level: u8,
+    progress_level: ProgressLevel,
 }
 
 //--------------------------------------------------------------------
@@ -226,16 +541,19 @@ pub struct MoveYourCmd {
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Ai {
     my: Side,
+    timelimit: bool,
     pos: Position,
     mv_your: Option<Move>, // 直前の your 指し手
     progress_ply: u8,
-    progress_level: u8,
+    progress_level: ProgressLevel,
     progress_level_sub: u8,
     book_state: BookState,
 
     // drop 候補手と最善手を比較する際に必要となる値。
     // 原作ではこの値が局面ごとに初期化されないため、状態を保持しておく必要がある。
     naitou_best_src: u8,
+
+    eval_jitter: EvalJitter, // undo では巻き戻さない
 }
 
 impl Ai {
@@ -248,14 +566,102 @@ impl Ai {
 
         Self {
             my,
+            timelimit,
             pos,
             mv_your: None,
             progress_ply: 0,
-            progress_level: 0,
+            progress_level: ProgressLevel::OPENING,
             progress_level_sub: 0,
             book_state,
 
             naitou_best_src: 0,
+
+            eval_jitter: EvalJitter::default(),
+        }
+    }
+
+    /// 任意の局面から思考ルーチンを作る。局面解析用。
+    /// 定跡は使わず、序盤処理は終了済みとして扱う。
+    /// 進行度 progress_ply は局面の手数と無関係に 0 から始まる(from_position_seeded() を参照)。
+    pub fn from_position(pos: Position, my: Side, timelimit: bool) -> Self {
+        Self {
+            my,
+            timelimit,
+            pos,
+            mv_your: None,
+            progress_ply: 0,
+            progress_level: ProgressLevel::MIDDLE,
+            progress_level_sub: 0,
+            book_state: BookState::new(Formation::Nothing),
+
+            naitou_best_src: 0,
+
+            eval_jitter: EvalJitter::default(),
+        }
+    }
+
+    /// from_position() と同様だが、進行度を局面の手数から推定して設定する。
+    ///
+    /// 内部の進行度 progress_ply は対局開始時に 0 で、以後 my/your の指し手ごとに 1 ずつ増える
+    /// (100 で頭打ち)。よって平手初期局面からの対局では SFEN の手数 ply に対し
+    /// progress_ply == min(ply - 1, 100) となる。ここではこの関係を仮定する。
+    /// progress_level もこれに応じて、your 指し手後に行われる更新と同じ閾値で設定する。
+    pub fn from_position_seeded(pos: Position, my: Side, timelimit: bool) -> Self {
+        let progress_ply = num_traits::clamp(pos.ply() - 1, 0, 100) as u8;
+
+        let mut this = Self::from_position(pos, my, timelimit);
+        this.progress_ply = progress_ply;
+        this.progress_level.advance_for_ply(progress_ply);
+
+        this
+    }
+
+    /// 候補手評価に揺らぎ (各項目に [-amount, amount] の一様乱数) を加えるようにする。
+    /// 乱数列は seed により決まる。amount == 0 なら原作通り。
+    ///
+    /// amount != 0 では原作と異なる手を指しうるので、エミュレータとの照合には使えない。
+    pub fn with_eval_jitter(mut self, amount: u8, seed: u64) -> Self {
+        self.eval_jitter = EvalJitter::new(amount, seed);
+        self
+    }
+
+    /// 棋譜の先頭 up_to_ply 手を再生した思考ルーチンを作る。
+    /// my 側の手は思考ルーチン自身の指し手と一致するか検証される。
+    /// 終局を表すエントリに達したらそこで再生を打ち切る。
+    pub fn from_record(record: &Record, up_to_ply: usize) -> Result<Self> {
+        let mut ai = Self::new(record.handicap(), record.timelimit());
+
+        for entry in record.entrys().iter().take(up_to_ply) {
+            match entry {
+                RecordEntry::Move(mv) | RecordEntry::MyWin(mv) => ai.replay_move(mv)?,
+                RecordEntry::YourSuicide | RecordEntry::YourWin => break,
+            }
+        }
+
+        Ok(ai)
+    }
+
+    /// 棋譜上の指し手 mv を再生する。
+    /// my 側の手番なら思考を行い、その結果が mv と一致するか検証してから適用する。
+    /// your 側の手番なら疑似合法手であることを確かめてから適用する。
+    pub fn replay_move(&mut self, mv: &Move) -> Result<()> {
+        if self.is_your_turn() {
+            if !your_move::is_pseudo_legal(&self.pos, mv) {
+                return Err(Error::illegal_move(mv, "your move is not pseudo-legal"));
+            }
+            self.move_your(mv);
+            return Ok(());
+        }
+
+        match self.think(&mut NullLogger::new()) {
+            RecordEntry::Move(mv_actual) | RecordEntry::MyWin(mv_actual) if mv_actual == *mv => {
+                self.move_my(mv);
+                Ok(())
+            }
+            entry => Err(Error::illegal_move(
+                mv,
+                format!("move mismatch (actual: {})", entry),
+            )),
         }
     }
 
@@ -263,10 +669,103 @@ impl Ai {
         self.my
     }
 
+    /// 時間制限の有無を返す。
+    /// 平手の場合、これにより初期戦型が変わる(Formation::from_handicap() を参照)。
+    pub fn timelimit(&self) -> bool {
+        self.timelimit
+    }
+
     pub fn pos(&self) -> &Position {
         &self.pos
     }
 
+    /// my 側が既に負けている (your 側の手番で、your 側が my 玉を取れる) かどうかを返す。
+    /// my 玉が盤上にない場合も負けとみなす。
+    ///
+    /// 原作の思考ルーチンはこの状態を想定していないので、対局ループ側で終局させること。
+    pub fn is_lost(&self) -> bool {
+        if self.pos.king(self.my).is_none() {
+            return true;
+        }
+        self.is_your_turn() && self.pos.can_capture_king()
+    }
+
+    /// 内部状態を全て JSON として w に書き出す。
+    /// SFEN では表せない進行度、定跡処理の状態、naitou_best_src なども含む。
+    #[cfg(feature = "save")]
+    pub fn save(&self, w: impl std::io::Write) -> Result<()> {
+        let data = AiSaveData {
+            version: AI_SAVE_VERSION,
+            my: self.my,
+            timelimit: self.timelimit,
+            sfen: sfen::position_to_sfen(&self.pos).into_owned(),
+            mv_your: self
+                .mv_your
+                .as_ref()
+                .map(|mv| sfen::move_to_sfen(mv).into_owned()),
+            progress_ply: self.progress_ply,
+            progress_level: self.progress_level.get(),
+            progress_level_sub: self.progress_level_sub,
+            book_state: self.book_state.clone(),
+            naitou_best_src: self.naitou_best_src,
+            eval_jitter_amount: self.eval_jitter.amount,
+            eval_jitter_state: self.eval_jitter.state,
+        };
+
+        serde_json::to_writer(w, &data).map_err(std::io::Error::from)?;
+
+        Ok(())
+    }
+
+    /// save() で書き出した内部状態を読み込む。
+    #[cfg(feature = "save")]
+    pub fn load(r: impl std::io::Read) -> Result<Self> {
+        fn invalid_data(msg: String) -> Error {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, msg).into()
+        }
+
+        let data: AiSaveData = serde_json::from_reader(r).map_err(std::io::Error::from)?;
+        if data.version != AI_SAVE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported save version: {}",
+                data.version
+            )));
+        }
+        if data.progress_level > 3 {
+            return Err(invalid_data(format!(
+                "invalid progress level: {}",
+                data.progress_level
+            )));
+        }
+
+        let pos = sfen::sfen_to_position_strict(&data.sfen)?;
+        let mv_your = data.mv_your.map(sfen::sfen_to_move).transpose()?;
+
+        Ok(Self {
+            my: data.my,
+            timelimit: data.timelimit,
+            pos,
+            mv_your,
+            progress_ply: data.progress_ply,
+            progress_level: ProgressLevel::new(data.progress_level),
+            progress_level_sub: data.progress_level_sub,
+            book_state: data.book_state,
+
+            naitou_best_src: data.naitou_best_src,
+
+            eval_jitter: EvalJitter {
+                amount: data.eval_jitter_amount,
+                state: data.eval_jitter_state,
+            },
+        })
+    }
+
+    /// 現局面の利き情報を my 側から見て計算する。
+    /// think() がルート局面で評価に用いるものと同じ。
+    pub fn root_effect_board(&self) -> EffectBoard {
+        EffectBoard::from_board(self.pos.board(), self.my)
+    }
+
     pub fn is_my_turn(&self) -> bool {
         self.pos.side() == self.my
     }
@@ -283,10 +782,43 @@ impl Ai {
         self.progress_ply = std::cmp::min(100, self.progress_ply + 1);
     }
 
-    pub fn progress_level(&self) -> u8 {
+    pub fn progress_level(&self) -> ProgressLevel {
         self.progress_level
     }
 
+    /// 序盤処理中 (進行度レベル 0 で、戦型が定まっている) かどうかを返す。
+    /// この間の思考ルーチンの手は定跡による可能性がある。
+    pub fn in_opening(&self) -> bool {
+        self.progress_level.is_opening() && self.book_state.formation() != Formation::Nothing
+    }
+
+    pub fn book_state(&self) -> &BookState {
+        &self.book_state
+    }
+
+    /// your 側の指し手 (あれば) を適用してから思考し、my 側の指し手を適用する。
+    /// (思考結果, my 指し手の MoveCmd) を返す。my 側が指さなかった場合 MoveCmd は None。
+    ///
+    /// 探索で局面を戻す必要がある場合は move_your(), step_my() などを個別に使うこと。
+    pub fn play(&mut self, your_move: Option<Move>) -> (RecordEntry, Option<MoveCmd>) {
+        self.play_with_logger(your_move, &mut NullLogger::new())
+    }
+
+    /// play() と同様だが、思考内容を logger に記録する。
+    pub fn play_with_logger<L: LoggerTrait>(
+        &mut self,
+        your_move: Option<Move>,
+        logger: &mut L,
+    ) -> (RecordEntry, Option<MoveCmd>) {
+        if let Some(mv) = your_move {
+            self.move_your(&mv);
+        }
+
+        let (entry, step_my_cmd) = self.step_my(logger);
+
+        (entry, step_my_cmd.mv_cmd)
+    }
+
     /// think(), move_my() を一括で行い、(RecordEntry, StepMyCmd) を返す。
     pub fn step_my<L: LoggerTrait>(&mut self, logger: &mut L) -> (RecordEntry, StepMyCmd) {
         let progress_ply = self.progress_ply;
@@ -350,13 +882,7 @@ impl Ai {
         self.mv_your = Some(mv.clone());
         self.increment_progress_ply();
 
-        if self.progress_ply >= 51 {
-            self.progress_level = std::cmp::min(2, self.progress_level + 1);
-        }
-
-        if self.progress_ply >= 71 {
-            self.progress_level = 3;
-        }
+        self.progress_level.advance_for_ply(self.progress_ply);
 
         MoveYourCmd {
             mv_cmd,
@@ -374,9 +900,51 @@ impl Ai {
     }
 
     pub fn think<L: LoggerTrait>(&mut self, logger: &mut L) -> RecordEntry {
+        self.think_impl(logger, &CandLimit::default())
+    }
+
+    /// think() と同じだが、結果を ThinkOutcome として返す。
+    pub fn think_outcome<L: LoggerTrait>(&mut self, logger: &mut L) -> ThinkOutcome {
+        self.think(logger).into()
+    }
+
+    /// 評価する候補手の数を max_candidates 個までに制限して思考する。
+    /// それまでに得られた最善手を返す。原作の挙動とは異なるので、プロファイリングなど用。
+    ///
+    /// 評価した候補手が全て却下された場合は投了 (RecordEntry::YourWin) となる。
+    pub fn think_limited<L: LoggerTrait>(
+        &mut self,
+        logger: &mut L,
+        max_candidates: usize,
+    ) -> RecordEntry {
+        let limit = CandLimit {
+            max_cands: Some(max_candidates),
+            ..CandLimit::default()
+        };
+        self.think_impl(logger, &limit)
+    }
+
+    /// 候補手を filter を満たすものに制限して思考する。原作の挙動とは異なるので、解析用。
+    /// 例えば「駒を取る手のうちどれを選ぶか」を調べられる。
+    ///
+    /// 定跡手も filter を満たさなければ採用せず、候補手中の最善手を指す (定跡の進行状態は通常通り進む)。
+    /// filter を満たす候補手が全て却下された (または存在しない) 場合は投了 (RecordEntry::YourWin) となる。
+    pub fn think_with_filter<L: LoggerTrait>(
+        &mut self,
+        logger: &mut L,
+        filter: impl Fn(&Move) -> bool,
+    ) -> RecordEntry {
+        let limit = CandLimit {
+            filter: Some(&filter),
+            ..CandLimit::default()
+        };
+        self.think_impl(logger, &limit)
+    }
+
+    fn think_impl<L: LoggerTrait>(&mut self, logger: &mut L, limit: &CandLimit) -> RecordEntry {
         let my = self.my;
 
-        let (entry, is_mate_your) = self.think_go(logger);
+        let (entry, is_mate_your) = self.think_go_impl(logger, limit);
 
         let entry = match entry {
             RecordEntry::YourSuicide | RecordEntry::YourWin => entry,
@@ -402,34 +970,50 @@ impl Ai {
     /// (思考結果, is_mate_your) を返す。
     /// 内部局面自体は更新しない。
     pub fn think_go<L: LoggerTrait>(&mut self, logger: &mut L) -> (RecordEntry, bool) {
-        // 6 手目以前で必ず序盤処理を行う your 側指し手 (dst) たち (my 側が先手の場合)
-        const DSTS_SPECIAL: &[Sq] = &[Sq::from_xy(4, 5), Sq::from_xy(5, 4), Sq::from_xy(2, 8)];
+        self.think_go_impl(logger, &CandLimit::default())
+    }
 
+    fn think_go_impl<L: LoggerTrait>(
+        &mut self,
+        logger: &mut L,
+        limit: &CandLimit,
+    ) -> (RecordEntry, bool) {
         let my = self.my;
         assert_eq!(self.pos.side(), my);
 
         logger.log_progress(
             self.progress_ply,
-            self.progress_level,
+            self.progress_level.get(),
             self.progress_level_sub,
         );
         logger.log_book_state(self.book_state.clone());
 
-        let (mv_best, root_eval, best_eval, is_mate_your) = self.think_nonbook(logger);
+        let (mv_best, root_eval, best_eval, is_mate_your) = self.think_nonbook(logger, limit);
         //dbg!(&mv_best, &root_eval, &best_eval);
 
         // 6 手目以前の特定の your 指し手に対しては必ず序盤処理を行う
+        let mut book_filtered = false;
         {
-            let cond = self.progress_ply <= 6
-                && self.mv_your.as_ref().map_or(false, |mv| {
-                    DSTS_SPECIAL.iter().any(|dst| *dst == mv.dst().rel(my))
-                });
-            if cond && self.progress_level == 0 {
-                let mv = self.process_opening();
-                if let Some(mv) = mv {
-                    return (RecordEntry::Move(mv), is_mate_your);
+            let cond = self
+                .mv_your
+                .as_ref()
+                .map_or(false, |mv| self.is_opening_trigger(mv));
+            if cond && self.progress_level.is_opening() {
+                match self.process_opening() {
+                    Some(mv) if limit.allows(&mv) => {
+                        logger.log_comment(format!("book: {} 手順", self.book_state.formation));
+                        return (RecordEntry::Move(mv), is_mate_your);
+                    }
+                    Some(_) => {
+                        // 定跡手が候補手の制限で除外された場合、序盤は終了させない
+                        logger.log_comment("book: skip (候補手の制限)".to_owned());
+                        book_filtered = true;
+                    }
+                    None => {
+                        logger.log_comment("book: 定跡終了".to_owned());
+                        self.progress_level.end_opening();
+                    }
                 }
-                self.progress_level = 1;
             }
         }
 
@@ -441,8 +1025,17 @@ impl Ai {
             return (RecordEntry::YourWin, is_mate_your);
         }
 
-        // この時点で mv_best は None ではないはず
-        let mv_best = mv_best.unwrap();
+        // 候補手を制限していなければ、この時点で mv_best は None ではないはず
+        let mv_best = match mv_best {
+            Some(mv) => mv,
+            None => {
+                assert!(limit.is_limited());
+                return (RecordEntry::YourWin, is_mate_your);
+            }
+        };
+        if book_filtered {
+            return (RecordEntry::Move(mv_best), is_mate_your);
+        }
 
         // 駒がぶつかったか?
         let nonquiet =
@@ -450,35 +1043,52 @@ impl Ai {
 
         // progress_level == 0 のとき、駒がぶつかるたびにサブ進行度を進める
         // サブ進行度が 5 になったら progress_level = 1 とする
-        if self.progress_level == 0 && nonquiet {
-            self.progress_level_sub += 1;
-            if self.progress_level_sub >= 5 {
-                self.progress_level = 1;
-            }
+        if nonquiet {
+            self.progress_level
+                .bump_on_exchange(&mut self.progress_level_sub);
         }
 
         // progress_level > 0 であるか、駒がぶつかったら序盤処理をスキップ
-        if self.progress_level > 0 || nonquiet {
+        if !self.progress_level.is_opening() {
+            return (RecordEntry::Move(mv_best), is_mate_your);
+        }
+        if nonquiet {
+            logger.log_comment("book: skip (駒がぶつかった)".to_owned());
             return (RecordEntry::Move(mv_best), is_mate_your);
         }
 
         // posi 値によっては序盤処理をスキップ
         if best_eval.posi != best_eval.adv_price && best_eval.posi >= 8 {
+            logger.log_comment(format!("book: skip (posi={})", best_eval.posi));
             return (RecordEntry::Move(mv_best), is_mate_your);
         }
 
         // 序盤処理
-        if self.progress_level == 0 {
-            let mv = self.process_opening();
-            if let Some(mv) = mv {
-                return (RecordEntry::Move(mv), is_mate_your);
+        if self.progress_level.is_opening() {
+            match self.process_opening() {
+                Some(mv) if limit.allows(&mv) => {
+                    logger.log_comment(format!("book: {} 手順", self.book_state.formation));
+                    return (RecordEntry::Move(mv), is_mate_your);
+                }
+                Some(_) => {
+                    logger.log_comment("book: skip (候補手の制限)".to_owned());
+                }
+                None => {
+                    logger.log_comment("book: 定跡終了".to_owned());
+                    self.progress_level.end_opening();
+                }
             }
-            self.progress_level = 1;
         }
 
         (RecordEntry::Move(mv_best), is_mate_your)
     }
 
+    /// 直前の your 指し手 mv_your が序盤処理を強制するものかどうかを返す。
+    /// 6 手目以前に OPENING_SPECIAL_DSTS のいずれかへ指した手が該当する。
+    pub fn is_opening_trigger(&self, mv_your: &Move) -> bool {
+        self.progress_ply <= 6 && OPENING_SPECIAL_DSTS.contains(&mv_your.dst().rel(self.my))
+    }
+
     /// 定跡手を取得する。
     /// 合法手かつ駒損のおそれがないかどうかの検査を行う。
     ///
@@ -531,9 +1141,12 @@ impl Ai {
     ///
     /// (最善手, ルート局面評価, 最善手評価, is_mate_your) を返す。
     /// ルート局面が既に勝ち(your 玉が取れる)であるか、指せる手がなければ最善手として None を返す。
+    ///
+    /// limit で候補手を制限できる (CandLimit を参照)。
     fn think_nonbook<L: LoggerTrait>(
         &mut self,
         logger: &mut L,
+        limit: &CandLimit,
     ) -> (Option<Move>, RootEval, BestEval, bool) {
         let my = self.my;
 
@@ -553,7 +1166,10 @@ impl Ai {
         let mut mv_best = None;
         let mut is_mate_your = false;
 
-        let cands: Vec<_> = my_move::moves_pseudo_legal(&self.pos).collect();
+        let cands: Vec<_> = my_move::moves_pseudo_legal(&self.pos)
+            .filter(|mv| limit.allows(mv))
+            .take(limit.max_cands.unwrap_or(usize::MAX))
+            .collect();
         for mv_cand in cands {
             logger.start_cand(mv_cand.clone());
 
@@ -582,6 +1198,173 @@ impl Ai {
         (mv_best, root_eval, best_eval, is_mate_your)
     }
 
+    /// 現局面の全候補手について (候補手, 修正後の評価値, 最善手を更新したか) を返す。
+    /// 順序は think_nonbook での候補手の評価順と同じ。
+    /// 却下された候補手は、却下された時点での評価値となる。
+    ///
+    /// think_nonbook と同様、your 玉を詰ます手が見つかった時点で打ち切る。
+    /// ルート局面が既に勝ち(your 玉が取れる)の場合、空の Vec を返す。
+    /// 評価値の揺らぎが有効なら、次に思考した場合と同じ揺らぎが加わる。
+    /// 内部状態は変更しない (揺らぎの乱数状態も元に戻す)。
+    pub fn candidate_evals(&mut self) -> Vec<(Move, CandEval, bool)> {
+        let my = self.my;
+        assert_eq!(self.pos.side(), my);
+
+        let eff_board = EffectBoard::from_board(self.pos.board(), my);
+        let root_eval = self.eval_root(&eff_board);
+        if root_eval.adv_price >= 30 {
+            return Vec::new();
+        }
+
+        let naitou_best_src = self.naitou_best_src;
+        let eval_jitter = self.eval_jitter.clone();
+        let mut best_eval = BestEval::default();
+        let mut logger = NullLogger::new();
+        let mut res = Vec::new();
+
+        let cands: Vec<_> = my_move::moves_pseudo_legal(&self.pos).collect();
+        for mv_cand in cands {
+            let cand = CandInfo::from_pos_mv(&self.pos, &mv_cand);
+
+            let (improved, cand_is_mate_your, _pos_eval, cand_eval) =
+                self.try_improve_best(&root_eval, &mut best_eval, &cand, &mut logger);
+
+            let is_best = improved || cand_is_mate_your;
+            if is_best {
+                self.update_naitou_best_src(&mv_cand);
+            }
+            res.push((mv_cand, cand_eval, is_best));
+            if cand_is_mate_your {
+                break;
+            }
+        }
+
+        self.naitou_best_src = naitou_best_src;
+        self.eval_jitter = eval_jitter;
+
+        res
+    }
+
+    /// 現局面において候補手 a, b のどちらを思考ルーチンが好むかを返す。
+    /// a が好まれるなら Greater、b が好まれるなら Less、両方とも却下されるなら Equal。
+    ///
+    /// 候補手ループで a, b の順に評価された場合と同じ判定を行う(同点なら先に評価された a が残る)。
+    /// a, b は my 側の pseudo-legal でなければならない。
+    /// 内部状態は変更しない (評価値の揺らぎの乱数状態も元に戻す)。
+    pub fn compare_moves(&mut self, a: &Move, b: &Move) -> Ordering {
+        let my = self.my;
+        assert_eq!(self.pos.side(), my);
+
+        let eff_board = EffectBoard::from_board(self.pos.board(), my);
+        let root_eval = self.eval_root(&eff_board);
+
+        let naitou_best_src = self.naitou_best_src;
+        let eval_jitter = self.eval_jitter.clone();
+        let mut best_eval = BestEval::default();
+        let mut logger = NullLogger::new();
+
+        let cand_a = CandInfo::from_pos_mv(&self.pos, a);
+        let (improved_a, is_mate_your_a, _, _) =
+            self.try_improve_best(&root_eval, &mut best_eval, &cand_a, &mut logger);
+        let res = if is_mate_your_a {
+            Ordering::Greater
+        } else {
+            if improved_a {
+                self.update_naitou_best_src(a);
+            }
+            let cand_b = CandInfo::from_pos_mv(&self.pos, b);
+            let (improved_b, is_mate_your_b, _, _) =
+                self.try_improve_best(&root_eval, &mut best_eval, &cand_b, &mut logger);
+            match (improved_a, improved_b || is_mate_your_b) {
+                (_, true) => Ordering::Less,
+                (true, false) => Ordering::Greater,
+                (false, false) => Ordering::Equal,
+            }
+        };
+
+        self.naitou_best_src = naitou_best_src;
+        self.eval_jitter = eval_jitter;
+
+        res
+    }
+
+    /// 現局面で持駒 pt を打つ合法手のうち、思考ルーチンが最も好むものを (打つマス, 候補手評価) として返す。
+    /// 打つ手がなければ None を返す。my 側の手番でなければならない。
+    ///
+    /// 候補手は思考時と同じ順序で比較され、naitou_best_src の状態も比較に影響する。
+    /// 呼び出し後、naitou_best_src と評価値の揺らぎの乱数状態は元に戻される。
+    pub fn best_drop(&mut self, pt: Piece) -> Option<(Sq, CandEval)> {
+        let my = self.my;
+        assert_eq!(self.pos.side(), my);
+
+        let eff_board = EffectBoard::from_board(self.pos.board(), my);
+        let root_eval = self.eval_root(&eff_board);
+
+        let naitou_best_src = self.naitou_best_src;
+        let eval_jitter = self.eval_jitter.clone();
+        let mut best_eval = BestEval::default();
+        let mut logger = NullLogger::new();
+
+        let mvs: Vec<_> = my_move::moves_drop(&self.pos, pt)
+            .into_iter()
+            .filter(|mv| my_move::can_drop(&self.pos, pt, mv.dst()))
+            .collect();
+
+        let mut res = None;
+        for mv in mvs {
+            let cand = CandInfo::from_pos_mv(&self.pos, &mv);
+            let (improved, is_mate_your, _, cand_eval) =
+                self.try_improve_best(&root_eval, &mut best_eval, &cand, &mut logger);
+            if is_mate_your {
+                res = Some((mv.dst(), cand_eval));
+                break;
+            }
+            if improved {
+                self.update_naitou_best_src(&mv);
+                res = Some((mv.dst(), cand_eval));
+            }
+        }
+
+        self.naitou_best_src = naitou_best_src;
+        self.eval_jitter = eval_jitter;
+
+        res
+    }
+
+    /// 現局面で my 側の候補手 mv を評価し、(修正後の候補手評価, 修正の記録) を返す。
+    /// 却下された場合、評価値は却下された時点でのものとなる。
+    ///
+    /// 最善手との比較は行わない。評価値の揺らぎは加えない。
+    /// mv は my 側の pseudo-legal でなければならない。内部状態は変更しない。
+    pub fn trace_tweak(&mut self, mv: &Move) -> (CandEval, TweakTrace) {
+        let my = self.my;
+        assert_eq!(self.pos.side(), my);
+
+        let eff_board = EffectBoard::from_board(self.pos.board(), my);
+        let root_eval = self.eval_root(&eff_board);
+        let cand = CandInfo::from_pos_mv(&self.pos, mv);
+
+        let cmd_cand = self.pos.do_move(mv).unwrap();
+
+        let eff_board = EffectBoard::from_board(self.pos.board(), my);
+        let (pos_eval, cand_eval) = self.eval_position(&eff_board, Some(&cand));
+        let mut cand_eval = cand_eval.unwrap();
+
+        let mut trace = TweakTrace::new(cand_eval.clone());
+        self.tweak_eval(
+            &root_eval,
+            &pos_eval,
+            &mut cand_eval,
+            &cand,
+            &mut NullLogger::new(),
+            Some(&mut trace),
+        );
+
+        self.pos.undo_move(&cmd_cand).unwrap();
+
+        (cand_eval, trace)
+    }
+
     /// ルート局面評価
     pub fn eval_root(&self, eff_board: &EffectBoard) -> RootEval {
         let my = self.my;
@@ -602,17 +1385,16 @@ impl Ai {
 
     /// (rbp, power) を返す。
     /// 理論上オーバーフローがありうることに注意。
+    ///
+    /// 原作通りラップアラウンドする。思考 (eval_root()) ではこちらを使うこと。
+    /// 表示用には eval_power_saturating() を使う。
     fn eval_power(&self, pos: &Position, side: Side, n_promoted: u8) -> (u8, u8) {
         let rbp: u8 = pos.hand(side)[Piece::Rook] + pos.hand(side)[Piece::Bishop] + n_promoted;
         let gs: u8 = pos.hand(side)[Piece::Gold] + pos.hand(side)[Piece::Silver];
         let kl: u8 = pos.hand(side)[Piece::Knight] + pos.hand(side)[Piece::Lance];
         let p: u8 = pos.hand(side)[Piece::Pawn];
 
-        // 手数補正 (77 手目以降かどうかで係数が変わる)
-        let mut ply_factor = self.progress_ply / 11;
-        if ply_factor >= 7 {
-            ply_factor *= 2;
-        }
+        let ply_factor = power_ply_factor(self.progress_ply);
 
         let mut power: u8 = 0;
         power.wadd(rbp.wrapping_mul(8));
@@ -624,6 +1406,76 @@ impl Ai {
         (rbp, power)
     }
 
+    /// 現局面における side 側の戦力評価 (power) を、ラップアラウンドせず 255 で飽和させて返す。
+    /// 持駒や成駒が増えれば値が減ることはないので、強さの目安として表示するのに使える。
+    ///
+    /// 原作の動作とは一致しないので、思考ルーチンでは使わない (eval_power() を参照)。
+    pub fn eval_power_saturating(&self, side: Side) -> u8 {
+        let (n_promoted_my, n_promoted_your) = self.eval_n_promoted(self.pos.board());
+        let n_promoted = if side == self.my {
+            n_promoted_my
+        } else {
+            n_promoted_your
+        };
+
+        let hand = self.pos.hand(side);
+        let rbp = hand[Piece::Rook]
+            .saturating_add(hand[Piece::Bishop])
+            .saturating_add(n_promoted);
+        let gs = hand[Piece::Gold].saturating_add(hand[Piece::Silver]);
+        let kl = hand[Piece::Knight].saturating_add(hand[Piece::Lance]);
+        let p = hand[Piece::Pawn];
+
+        let ply_factor = power_ply_factor(self.progress_ply);
+
+        rbp.saturating_mul(8)
+            .saturating_add(gs.saturating_mul(4))
+            .saturating_add(kl.saturating_mul(2))
+            .saturating_add(p)
+            .saturating_add(ply_factor)
+    }
+
+    /// 局面 pos を my 側から見た単一の評価値 (正なら my 有利) を返す。
+    /// グラフ表示や USI の info score cp 向け。
+    ///
+    /// 思考ルーチンの候補手比較は辞書式であり、これはその近似に過ぎない (原作の判断とは必ずしも一致しない)。
+    /// 駒割りを主とし、駒得/駒損マス、玉周りの利き、成駒、離れ駒を加味する。
+    /// 駒得/駒損マスは、pos の手番にかかわらず my 側が指した直後の局面として評価する。
+    /// pos には両玉が存在しなければならない。
+    pub fn score(&self, pos: &Position) -> i32 {
+        let my = self.my;
+        let your = my.inv();
+
+        let mut ai = self.clone();
+        ai.pos = pos.clone();
+        let eff_board = EffectBoard::from_board(pos.board(), my);
+        let pos_eval = ai.eval_position(&eff_board, None).0;
+
+        // 玉を除く盤上の駒と持駒の価値の総和
+        let material = |side: Side| -> i32 {
+            let on_board: i32 = Sq::iter_valid()
+                .filter_map(|sq| pos.board()[sq].piece_of(side))
+                .filter(|&pt| pt != Piece::King)
+                .map(|pt| i32::from(PRICES_0[pt]))
+                .sum();
+            let in_hand: i32 = Piece::iter_hand()
+                .map(|pt| i32::from(pos.hand(side)[pt]) * i32::from(PRICES_0[pt]))
+                .sum();
+            on_board + in_hand
+        };
+
+        let v = |x: u8| i32::from(x);
+
+        let mut score = 10 * (material(my) - material(your));
+        score += 5 * v(pos_eval.adv_price) - 8 * v(pos_eval.disadv_price);
+        score += 2 * v(pos_eval.king_safety_far_my) - 3 * v(pos_eval.king_threat_far_my);
+        score += 2 * v(pos_eval.king_threat_far_your);
+        score += 3 * (v(pos_eval.n_promoted_my) - v(pos_eval.n_promoted_your));
+        score -= 2 * v(pos_eval.n_loose_my);
+
+        score
+    }
+
     /// 現局面を評価する。
     /// cand が与えられた場合、末端局面とみなし、CandEval も返す。
     pub fn eval_position(
@@ -743,7 +1595,7 @@ impl Ai {
                 if price_my < price_your {
                     true
                 } else if price_my == price_your {
-                    self.progress_level != 0
+                    !self.progress_level.is_opening()
                 } else {
                     false
                 }
@@ -898,14 +1750,10 @@ impl Ai {
     fn eval_n_promoted(&self, board: &Board) -> (u8, u8) {
         let mut n_promoted = [0, 0];
 
-        for sq in Sq::iter_valid() {
-            let cell = &board[sq];
-
-            for side in Side::iter() {
-                let idx = if side == self.my { 0 } else { 1 };
-                if cell.piece_of(side).map_or(false, |pt| pt.is_promoted()) {
-                    n_promoted[idx] += 1;
-                }
+        for (_, side, pt) in board.iter_pieces() {
+            let idx = if side == self.my { 0 } else { 1 };
+            if pt.is_promoted() {
+                n_promoted[idx] += 1;
             }
         }
 
@@ -940,7 +1788,7 @@ impl Ai {
 
             if dist_to_my == 1 {
                 king_threat_near_my += cell[your].count();
-                if cell[your].count() >= cell[my].count() {
+                if is_choked(cell, my) {
                     n_choke_my += 1;
                 }
             }
@@ -983,7 +1831,10 @@ impl Ai {
         logger.log_cand_pos_eval(pos_eval.clone());
         logger.log_cand_eval(cand_eval.clone());
 
-        let tweak_res = self.tweak_eval(root_eval, &pos_eval, &mut cand_eval, cand, logger);
+        let tweak_res = self.tweak_eval(root_eval, &pos_eval, &mut cand_eval, cand, logger, None);
+        if tweak_res != TweakResult::Reject {
+            self.eval_jitter.apply(&mut cand_eval);
+        }
 
         self.pos.undo_move(&cmd_cand).unwrap();
 
@@ -1025,12 +1876,24 @@ impl Ai {
         cand_eval: &mut CandEval,
         cand: &CandInfo,
         logger: &mut L,
+        mut trace: Option<&mut TweakTrace>,
     ) -> TweakResult {
-        macro_rules! log_cand_eval {
-            () => {
+        macro_rules! step {
+            ($rule:expr) => {
                 logger.log_cand_eval(cand_eval.clone());
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.record($rule, cand_eval);
+                }
             };
         }
+        macro_rules! reject {
+            ($rule:expr) => {{
+                if let Some(trace) = trace.as_deref_mut() {
+                    trace.rejected_by = Some($rule);
+                }
+                return TweakResult::Reject;
+            }};
+        }
 
         let my = self.my;
 
@@ -1052,7 +1915,7 @@ impl Ai {
         {
             match self.judge_mate_your(&cand.mv) {
                 MateJudge::Nonmate => {}
-                MateJudge::DropPawnMate => return TweakResult::Reject,
+                MateJudge::DropPawnMate => reject!("打ち歩詰め"),
                 MateJudge::Mate => {
                     // 詰ます手は明らかに最善なので、他の候補手に上書きされないよう評価値を細工
                     is_mate_your = true;
@@ -1062,7 +1925,7 @@ impl Ai {
                 }
             }
         }
-        log_cand_eval!();
+        step!("詰み判定");
 
         // 評価値修正パート
         // オーバーフローが起こりうるので注意
@@ -1074,19 +1937,19 @@ impl Ai {
         {
             cand_eval.nega.wsub(1);
         }
-        log_cand_eval!();
+        step!("歩で駒取り");
 
         // 原則として駒捨ては却下 (王手対応や詰ます手は除く)
         if cand_eval.is_sacrifice && root_eval.disadv_price < 30 && !is_mate_your {
-            return TweakResult::Reject;
+            reject!("駒捨て却下");
         }
-        log_cand_eval!();
+        step!("駒捨て却下");
 
         // your 側の垂れ歩/香が存在すればマイナス評価
         if pos_eval.hanging_your {
             cand_eval.nega.wadd(4);
         }
-        log_cand_eval!();
+        step!("your 側の垂れ歩/香");
 
         // 中盤以降は自玉から遠い歩を取られるのを軽視
         if (root_eval.power_my >= 15 || root_eval.power_your >= 15)
@@ -1095,7 +1958,7 @@ impl Ai {
         {
             cand_eval.nega.wsub(cand_eval.disadv_price);
         }
-        log_cand_eval!();
+        step!("自玉から遠い歩の損を軽視");
 
         // 終盤用追加処理
         if root_eval.power_my >= 25 || root_eval.power_your >= 25 {
@@ -1105,7 +1968,7 @@ impl Ai {
             {
                 cand_eval.posi.wsub(cand_eval.adv_price);
             }
-            log_cand_eval!();
+            step!("終盤: 玉から遠い最大駒得マス");
 
             // 互いの玉から遠い桂香を取られるのを軽視
             if cand_eval.disadv_price < 7
@@ -1114,7 +1977,7 @@ impl Ai {
             {
                 cand_eval.nega.wsub(cand_eval.disadv_price);
             }
-            log_cand_eval!();
+            step!("終盤: 玉から遠い桂香の損を軽視");
 
             // your 玉近くの駒を取る手の評価を上げる
             // 互いの玉から遠い駒を取る手の評価を下げる
@@ -1128,7 +1991,7 @@ impl Ai {
                 }
             }
         }
-        log_cand_eval!();
+        step!("終盤: 駒取りの玉距離補正");
 
         // 寄せが見込めない状況で無闇に王手を掛けないようにする
         // ただし「王手xx取り」ならOK
@@ -1140,7 +2003,7 @@ impl Ai {
         {
             cand_eval.posi.wsub(cand_eval.adv_price);
         }
-        log_cand_eval!();
+        step!("無闇な王手");
 
         // 高い駒を自陣側かつ my 玉から遠くに打つ手の評価を下げる (合駒は除く)
         if cand.mv.is_drop()
@@ -1155,7 +2018,7 @@ impl Ai {
         {
             cand_eval.nega.wadd(2);
         }
-        log_cand_eval!();
+        step!("自陣への高い駒打ち");
 
         // 意図がよくわからない
         if root_eval.power_my >= 27 {
@@ -1165,7 +2028,7 @@ impl Ai {
                 cand_eval.capture_price.wadd(4);
             }
         }
-        log_cand_eval!();
+        step!("posi による capture_price 加算 (power_my >= 27)");
 
         // 大駒を打つ手は敵陣側ほど評価を高くする (合駒の場合はペナルティなし)
         if cand.mv.is_drop() && matches!(cand.pt_dst, Piece::Rook | Piece::Bishop) {
@@ -1181,14 +2044,14 @@ impl Ai {
                 }
             }
         }
-        log_cand_eval!();
+        step!("大駒打ちの段");
 
         // 玉で駒を取る手は評価を下げる(なるべく他の駒で取る)
         if matches!(cand.pt_dst, Piece::King) {
             cand_eval.capture_price.wsub(1);
             cand_eval.posi.wsub(2);
         }
-        log_cand_eval!();
+        step!("玉で駒取り");
 
         // 意図がよくわからない
         // 最後の条件は sq_king_your を誤って sq_king_my にした疑惑もある
@@ -1200,14 +2063,14 @@ impl Ai {
         {
             cand_eval.posi.wadd((pos_eval.king_threat_far_your - 7) / 2);
         }
-        log_cand_eval!();
+        step!("king_threat_far_your による posi 加算");
 
         // 自分から角をぶつける手を避ける意図?
         if cand_eval.adv_price == 16 && matches!(cand.pt_dst, Piece::Bishop) {
             cand_eval.posi.wsub(cand_eval.adv_price);
             cand_eval.adv_price = 0;
         }
-        log_cand_eval!();
+        step!("角ぶつけ回避");
 
         // 戦力が豊富かつ自玉が危険なら大駒を温存せず直ちに使う意図?
         if root_eval.power_my >= 27
@@ -1216,7 +2079,7 @@ impl Ai {
             cand_eval.posi.wsub(4 * pos_eval.n_choke_my);
             cand_eval.nega.wadd(4 * pos_eval.n_choke_my);
         }
-        log_cand_eval!();
+        step!("自玉周りの詰まり");
 
         // 意図がよくわからない
         if cand_eval.capture_price >= 8
@@ -1239,7 +2102,7 @@ impl Ai {
                 }
             }
         }
-        log_cand_eval!();
+        step!("高い駒取り");
 
         // 自玉が危険な場合、玉で駒を取るのは価値なしとする
         //
@@ -1247,13 +2110,13 @@ impl Ai {
         if pos_eval.king_threat_near_my >= 5 && matches!(cand.pt_dst, Piece::King) {
             cand_eval.capture_price = 0;
         }
-        log_cand_eval!();
+        step!("自玉危険時の玉で駒取り");
 
         // 戦力が豊富なら駒を取りながらの王手の評価を上げる
         if root_eval.power_my >= 35 && cand_eval.adv_price >= 30 && cand_eval.capture_price >= 2 {
             cand_eval.nega.wsub(2);
         }
-        log_cand_eval!();
+        step!("駒取り王手 (power_my >= 35)");
 
         // 意図がよくわからない
         if root_eval.power_my >= 20 && cand_eval.capture_price < 2 {
@@ -1264,7 +2127,7 @@ impl Ai {
                 _ => cand_eval.capture_price.wadd(3),
             }
         }
-        log_cand_eval!();
+        step!("posi による capture_price 加算 (power_my >= 20)");
 
         // 飛/角を敵陣以外に打つ手の評価を下げる
         if cand.mv.is_drop()
@@ -1274,7 +2137,7 @@ impl Ai {
             cand_eval.posi.wsub(3);
             cand_eval.nega.wadd(3);
         }
-        log_cand_eval!();
+        step!("敵陣以外への飛角打ち");
 
         // 成駒を動かす場合、your 玉に近づく手の方を高く評価する
         if let Move::Nondrop(nondrop) = &cand.mv {
@@ -1284,7 +2147,7 @@ impl Ai {
                 cand_eval.posi.wadd(dd as u8);
             }
         }
-        log_cand_eval!();
+        step!("成駒の your 玉接近");
 
         // 戦力が豊富なら王手の評価を上げる
         if root_eval.power_my >= 25 && cand_eval.adv_price >= 30 {
@@ -1292,13 +2155,13 @@ impl Ai {
             cand_eval.capture_price.wadd(1);
             cand_eval.nega.wsub(2);
         }
-        log_cand_eval!();
+        step!("王手 (power_my >= 25)");
 
         // 高い駒を取りながらの王手の評価を上げる
         if cand_eval.adv_price >= 30 && cand_eval.capture_price >= 8 {
             cand_eval.nega.wsub(4);
         }
-        log_cand_eval!();
+        step!("高い駒取り王手");
 
         // 負の評価値を 0 に補正
         let chmax_zero = |x: &mut u8| {
@@ -1309,7 +2172,7 @@ impl Ai {
         chmax_zero(&mut cand_eval.capture_price);
         chmax_zero(&mut cand_eval.posi);
         chmax_zero(&mut cand_eval.nega);
-        log_cand_eval!();
+        step!("負の評価値を 0 に補正");
 
         if is_mate_your {
             TweakResult::YourMate
@@ -1470,3 +2333,190 @@ impl Ai {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_level() {
+        let mut level = ProgressLevel::OPENING;
+        level.advance_for_ply(50);
+        assert_eq!(level, ProgressLevel::OPENING);
+
+        let mut level_sub = 0;
+        for _ in 0..4 {
+            level.bump_on_exchange(&mut level_sub);
+        }
+        assert!(level.is_opening());
+        level.bump_on_exchange(&mut level_sub);
+        assert_eq!(level, ProgressLevel::MIDDLE);
+        level.bump_on_exchange(&mut level_sub);
+        assert_eq!(level_sub, 5);
+
+        level.advance_for_ply(51);
+        assert_eq!(level.get(), 2);
+        level.advance_for_ply(52);
+        assert_eq!(level.get(), 2);
+        level.advance_for_ply(71);
+        assert_eq!(level.get(), 3);
+
+        // 序盤のまま 51 手に達した場合は 1 段階だけ進む
+        let mut level = ProgressLevel::OPENING;
+        level.advance_for_ply(51);
+        assert_eq!(level, ProgressLevel::MIDDLE);
+    }
+
+    #[test]
+    fn test_trace_tweak() {
+        let pos = Position::from_sfen(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+        )
+        .unwrap();
+        let mut ai = Ai::from_position(pos, Side::Gote, false);
+
+        // 記録を最後まで辿ると修正後の評価値に一致する
+        for (mv, cand_eval, _) in ai.candidate_evals() {
+            let (cand_eval_trace, trace) = ai.trace_tweak(&mv);
+            assert_eq!(cand_eval_trace, cand_eval);
+            assert_eq!(trace.current(), &cand_eval);
+            for step in &trace.steps {
+                assert_ne!(step.before, step.after);
+            }
+        }
+    }
+
+    #[test]
+    fn test_think_with_filter() {
+        let pos = Position::from_sfen(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+        )
+        .unwrap();
+
+        // 選ばれる手は filter を満たす
+        let mut ai = Ai::from_position(pos.clone(), Side::Gote, false);
+        let entry = ai.think_with_filter(&mut NullLogger::new(), |mv| {
+            !mv.is_drop() && mv.dst().y().get() == 4
+        });
+        match entry {
+            RecordEntry::Move(mv) => assert_eq!(mv.dst().y().get(), 4),
+            _ => panic!("unexpected entry: {:?}", entry),
+        }
+
+        // filter を満たす手がなければ投了
+        let mut ai = Ai::from_position(pos, Side::Gote, false);
+        let entry = ai.think_with_filter(&mut NullLogger::new(), |_| false);
+        assert_eq!(entry, RecordEntry::YourWin);
+    }
+
+    #[test]
+    fn test_think_with_filter_keeps_opening() {
+        let mut ai = Ai::new(Handicap::YourSente, false);
+        ai.move_your(&Move::nondrop(Sq::from_xy(3, 7), Sq::from_xy(3, 6), false));
+
+        // 制限なしなら定跡手を指す
+        let mv_book = match ai.clone().think(&mut NullLogger::new()) {
+            RecordEntry::Move(mv) => mv,
+            entry => panic!("unexpected entry: {:?}", entry),
+        };
+
+        // 定跡手を除外しても序盤処理は終了しない
+        let entry = ai.think_with_filter(&mut NullLogger::new(), |mv| *mv != mv_book);
+        assert_ne!(entry, RecordEntry::Move(mv_book));
+        assert!(ai.in_opening());
+    }
+
+    #[test]
+    fn test_from_record_illegal_your_move() {
+        let mut record = Record::new(Handicap::YourSente, false);
+        record.add(RecordEntry::Move(Move::nondrop(
+            Sq::from_xy(5, 5),
+            Sq::from_xy(5, 4),
+            false,
+        )));
+        assert!(matches!(
+            Ai::from_record(&record, 1),
+            Err(Error::IllegalMove(..))
+        ));
+    }
+
+    #[test]
+    fn test_score() {
+        let ai = Ai::new(Handicap::MySente, false);
+
+        // 駒得している方が評価が高い
+        let pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K4 b - 1").unwrap();
+        let pos_rook_my = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K4 b R 1").unwrap();
+        let pos_rook_your = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K4 b r 1").unwrap();
+        assert!(ai.score(&pos_rook_my) > ai.score(&pos));
+        assert!(ai.score(&pos) > ai.score(&pos_rook_your));
+        assert!(ai.score(&pos_rook_my) > 0);
+        assert!(ai.score(&pos_rook_your) < 0);
+
+        // 駒損マスがあれば評価が下がる
+        let pos_hanging = Position::from_sfen("sfen 4k4/9/9/9/4r4/9/4G4/9/4K4 b - 1").unwrap();
+        let pos_safe = Position::from_sfen("sfen 4k4/9/9/9/4r4/9/9/4G4/4K4 b - 1").unwrap();
+        assert!(ai.score(&pos_safe) > ai.score(&pos_hanging));
+    }
+
+    #[test]
+    fn test_is_lost() {
+        // 後手番で後手の飛車が先手玉を取れる
+        let pos = Position::from_sfen("sfen 4k4/9/9/9/4r4/9/9/9/4K4 w - 1").unwrap();
+        assert!(Ai::from_position(pos.clone(), Side::Sente, false).is_lost());
+        assert!(!Ai::from_position(pos, Side::Gote, false).is_lost());
+
+        // 先手番なら王手を受ければよいので負けではない
+        let pos = Position::from_sfen("sfen 4k4/9/9/9/4r4/9/9/9/4K4 b - 1").unwrap();
+        assert!(!Ai::from_position(pos, Side::Sente, false).is_lost());
+    }
+
+    #[cfg(feature = "save")]
+    #[test]
+    fn test_save_load() {
+        let mut ai = Ai::new(Handicap::YourSente, false).with_eval_jitter(2, 1);
+        for _ in 0..10 {
+            let mut pos = ai.pos().clone();
+            let mv = your_move::moves_legal(&mut pos).next().unwrap();
+            if !matches!(ai.play(Some(mv)).0, RecordEntry::Move(_)) {
+                break;
+            }
+        }
+
+        let mut buf = Vec::new();
+        ai.save(&mut buf).unwrap();
+        assert_eq!(Ai::load(buf.as_slice()).unwrap(), ai);
+
+        assert!(Ai::load(&b"{}"[..]).is_err());
+    }
+
+    #[test]
+    fn test_eval_jitter_restored() {
+        // 評価値の揺らぎがあっても、候補手の調査は思考結果に影響しない
+        let pos = Position::from_sfen(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL w - 2",
+        )
+        .unwrap();
+        let mut ai = Ai::from_position(pos, Side::Gote, false).with_eval_jitter(3, 1);
+        let ai_orig = ai.clone();
+
+        let evals = ai.candidate_evals();
+        assert_eq!(ai.candidate_evals(), evals);
+        let (a, b) = (evals[0].0.clone(), evals[1].0.clone());
+        ai.compare_moves(&a, &b);
+        ai.best_drop(Piece::Pawn);
+        assert_eq!(ai, ai_orig);
+    }
+
+    #[test]
+    fn test_eval_jitter() {
+        let mut jitter = EvalJitter::new(0, 1);
+        assert!((0..100).all(|_| jitter.perturb(10) == 10));
+
+        let mut jitter = EvalJitter::new(3, 1);
+        let values: Vec<_> = (0..100).map(|_| jitter.perturb(10)).collect();
+        assert!(values.iter().all(|v| (7..=13).contains(v)));
+        assert!(values.iter().any(|&v| v != 10));
+        assert!((0..100).all(|_| jitter.perturb(0) <= 3));
+    }
+}
@@ -0,0 +1,770 @@
+//!===================================================================
+//! 局面解析
+//!
+//! 任意の局面に対して思考ルーチンを走らせる。
+//!===================================================================
+
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
+
+use crate::ai::{self, Ai, RootEval};
+use crate::effect::EffectBoard;
+use crate::log::NullLogger;
+#[cfg(any(test, feature = "test-util"))]
+use crate::my_move;
+use crate::position;
+use crate::prelude::*;
+use crate::price::PRICES_0;
+use crate::record::{Record, RecordEntry};
+#[cfg(any(test, feature = "test-util"))]
+use crate::sfen;
+use crate::your_move::{self, IllegalPolicy};
+use crate::your_player::{YourPlayer, YourPlayerRandom};
+
+/// 局面 pos を side 側 (思考ルーチン側) から評価し、(思考結果, ルート局面評価) を返す。
+/// pos の手番は side でなければならない。
+pub fn evaluate(pos: &Position, side: Side, timelimit: bool) -> (RecordEntry, RootEval) {
+    let mut ai = Ai::from_position(pos.clone(), side, timelimit);
+
+    let eff_board = EffectBoard::from_board(pos.board(), side);
+    let root_eval = ai.eval_root(&eff_board);
+
+    let entry = ai.think(&mut NullLogger::new());
+
+    (entry, root_eval)
+}
+
+/// 複数の局面を並列に評価する。結果の順序は positions と同じ。
+/// 各局面は独立した Ai で評価される。
+///
+/// CPU バウンドな処理であり、スレッド数は rayon のスレッドプールに従う。
+pub fn evaluate_many(
+    positions: &[(Position, Side)],
+    timelimit: bool,
+) -> Vec<(RecordEntry, RootEval)> {
+    positions
+        .par_iter()
+        .map(|(pos, side)| evaluate(pos, *side, timelimit))
+        .collect()
+}
+
+//--------------------------------------------------------------------
+// 対局ループ
+//--------------------------------------------------------------------
+
+/// 対局の終了理由
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GameEnd {
+    Entry(RecordEntry), // 思考ルーチンによる終局 (MyWin, YourSuicide, YourWin)
+    MyLost,             // your 側が my 玉を取れる状態になった (Ai::is_lost())
+    YourSuspend,        // your 側が手を返さなかった
+    MaxPly,             // 手数上限に達した
+    Repetition,         // 同一局面が 4 回現れた
+}
+
+/// 思考ルーチンと your 側プレイヤーを交互に指させる対局ループ。
+///
+/// 手番は局面から判断するので、my 側が先手か後手かを呼び出し側で気にする必要はない。
+/// イテレータとして (手数, 手番, 指し手) を順に返し、終局したら None を返す。
+/// 手数は指し手を指す前の局面の手数 (SFEN の手数と同じく 1 始まり)。
+/// 終了理由は end() で得られる。
+///
+/// 思考ルーチンの勝ち宣言 (MyWin) の場合、その指し手を返してから終局する。
+#[derive(Debug)]
+pub struct GameLoop<P: YourPlayer> {
+    ai: Ai,
+    player: P,
+    max_ply: Option<i32>,
+    keys: HashMap<u64, u32>,
+    end: Option<GameEnd>,
+}
+
+impl<P: YourPlayer> GameLoop<P> {
+    pub fn new(ai: Ai, player: P) -> Self {
+        let mut this = Self {
+            ai,
+            player,
+            max_ply: None,
+            keys: HashMap::new(),
+            end: None,
+        };
+        this.record_key();
+        this
+    }
+
+    /// 手数が max_ply を超える手は指さずに終局するようにする。
+    pub fn with_max_ply(mut self, max_ply: i32) -> Self {
+        self.max_ply = Some(max_ply);
+        self
+    }
+
+    pub fn ai(&self) -> &Ai {
+        &self.ai
+    }
+
+    /// 終了理由を返す。終局していなければ None。
+    pub fn end(&self) -> Option<&GameEnd> {
+        self.end.as_ref()
+    }
+
+    /// 現局面の出現回数を数え、4 回目なら千日手として終局させる。
+    fn record_key(&mut self) {
+        let count = self.keys.entry(self.ai.pos().zobrist()).or_insert(0);
+        *count += 1;
+        if *count >= 4 {
+            self.end = Some(GameEnd::Repetition);
+        }
+    }
+}
+
+impl<P: YourPlayer> Iterator for GameLoop<P> {
+    type Item = (i32, Side, Move);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.end.is_some() {
+            return None;
+        }
+
+        let ply = self.ai.pos().ply();
+        if let Some(max_ply) = self.max_ply {
+            if ply > max_ply {
+                self.end = Some(GameEnd::MaxPly);
+                return None;
+            }
+        }
+
+        if self.ai.is_lost() {
+            self.end = Some(GameEnd::MyLost);
+            return None;
+        }
+
+        let side = self.ai.pos().side();
+        let mv = if self.ai.is_your_turn() {
+            let mut pos = self.ai.pos().clone();
+            let mv = match self.player.think(&mut pos) {
+                Some(mv) => mv,
+                None => {
+                    self.end = Some(GameEnd::YourSuspend);
+                    return None;
+                }
+            };
+            self.ai.move_your(&mv);
+            mv
+        } else {
+            match self.ai.play(None) {
+                (RecordEntry::Move(mv), _) => mv,
+                (RecordEntry::MyWin(mv), _) => {
+                    self.end = Some(GameEnd::Entry(RecordEntry::MyWin(mv.clone())));
+                    return Some((ply, side, mv));
+                }
+                (entry, _) => {
+                    self.end = Some(GameEnd::Entry(entry));
+                    return None;
+                }
+            }
+        };
+
+        self.record_key();
+
+        Some((ply, side, mv))
+    }
+}
+
+/// 思考ルーチンとシード固定のランダムな your 側 (自殺手、打ち歩詰めは指さない) を
+/// 終局または max_ply 手まで対局させ、棋譜を返す。
+///
+/// 同じ引数なら常に同じ棋譜となるので、思考ルーチンの回帰テストに使える。
+pub fn deterministic_game(
+    handicap: Handicap,
+    timelimit: bool,
+    your_seed: u64,
+    max_ply: i32,
+) -> Record {
+    let ai = Ai::new(handicap, timelimit);
+    let player = YourPlayerRandom::with_seed(IllegalPolicy::Strict, your_seed);
+    let mut game = GameLoop::new(ai, player).with_max_ply(max_ply);
+
+    let mut entrys: Vec<_> = game
+        .by_ref()
+        .map(|(_, _, mv)| RecordEntry::Move(mv))
+        .collect();
+    match game.end() {
+        // MyWin の指し手はイテレータからも返されているので置き換える
+        Some(GameEnd::Entry(entry @ RecordEntry::MyWin(_))) => {
+            *entrys.last_mut().unwrap() = entry.clone();
+        }
+        Some(GameEnd::Entry(entry)) => entrys.push(entry.clone()),
+        _ => {}
+    }
+
+    let mut record = Record::new(handicap, timelimit);
+    for entry in entrys {
+        record.add(entry);
+    }
+
+    record
+}
+
+//--------------------------------------------------------------------
+// 玉の包囲網
+//--------------------------------------------------------------------
+
+/// your 側の玉の周囲 8 マス (盤外を除く) について、(マス, my 側が押さえているか) を列挙する。
+/// 判定は思考ルーチンの n_choke 評価と同じく ai::is_choked() による。
+/// your 側の玉がなければ空となる。
+pub fn mating_net(pos: &Position, your: Side) -> Vec<(Sq, bool)> {
+    let sq_king = unwrap_or_return!(ai::find_king_sq(pos.board(), your), Vec::new());
+    let eff_board = EffectBoard::from_board(pos.board(), your.inv());
+
+    sq_king
+        .neighbors()
+        .map(|sq| (sq, ai::is_choked(&eff_board[sq], your)))
+        .collect()
+}
+
+//--------------------------------------------------------------------
+// 指し手生成の照合
+//--------------------------------------------------------------------
+
+/// my 側と your 側の指し手生成器が pos の手番側について同じ合法手の集合を返すことを確かめる。
+/// 一致しなければ panic する。テスト用。
+///
+/// 両者はいずれも手番側の指し手を生成するので、同じ局面にそのまま適用して比較できる。
+/// 以下の違いは吸収する:
+///
+///   * 思考ルーチンは成れる場合は必ず成るので、your 側の成れるのに成らない手は除く。
+///   * 自殺手と打ち歩詰めは両者とも除く。
+///
+/// pos の手番側が相手玉を取れる局面では使えない。
+#[cfg(any(test, feature = "test-util"))]
+pub fn cross_check_generators(pos: &mut Position) {
+    let side = pos.side();
+
+    // 打ち歩詰めの判定は自殺手を除いてから行う (相手が玉を取れる局面では判定できない)
+    let mut mvs_my: Vec<_> = my_move::moves_pseudo_legal(pos).collect();
+    mvs_my.retain(|mv| position::with_move(pos, mv, |pos| !pos.can_capture_king()).unwrap());
+    let mvs_my: HashSet<_> = mvs_my
+        .into_iter()
+        .filter(|mv| match mv {
+            Move::Drop(drop) => my_move::can_drop(pos, drop.pt, drop.dst),
+            Move::Nondrop(_) => true,
+        })
+        .collect();
+
+    let mvs_your: HashSet<_> = your_move::moves_with_policy(pos, IllegalPolicy::Strict)
+        .into_iter()
+        .filter(|mv| match mv {
+            Move::Nondrop(nondrop) if !nondrop.is_promotion => {
+                let pt = pos.board()[nondrop.src].piece_of(side).unwrap();
+                !can_promote(side, pt, nondrop.src, nondrop.dst)
+            }
+            _ => true,
+        })
+        .collect();
+
+    assert_eq!(
+        mvs_my,
+        mvs_your,
+        "generators disagree: {}",
+        sfen::position_to_sfen(pos)
+    );
+}
+
+//--------------------------------------------------------------------
+// 到達可能局面
+//--------------------------------------------------------------------
+
+/// start から policy に従う指し手で plies 手以内に到達できる局面 (start 自身を含む) の
+/// Zobrist ハッシュ全体を返す。分岐数の測定や指し手生成の網羅性テストに使える。
+///
+/// 手数は含まないので、同一局面に異なる手順で到達しても 1 つと数える。
+/// 玉を取れる局面 (直前に自殺手が指された局面) からは展開しない。
+///
+/// 結果は plies に対して指数的に増えるので、plies が大きい場合はメモリに注意。
+/// 局面を保持せず数え上げるだけなら for_each_reachable() を使うこと。
+pub fn reachable_positions(start: Position, plies: u32, policy: IllegalPolicy) -> HashSet<u64> {
+    // 局面ごとに、到達時点での残り手数の最大値を記録する
+    let mut visited = HashMap::<u64, u32>::new();
+
+    fn dfs(pos: &mut Position, rest: u32, policy: IllegalPolicy, visited: &mut HashMap<u64, u32>) {
+        let key = pos.zobrist();
+        if let Some(&rest_visited) = visited.get(&key) {
+            if rest_visited >= rest {
+                return;
+            }
+        }
+        visited.insert(key, rest);
+
+        if rest == 0 || pos.can_capture_king() {
+            return;
+        }
+        for mv in your_move::moves_with_policy(pos, policy) {
+            position::with_move(pos, &mv, |pos| dfs(pos, rest - 1, policy, visited)).unwrap();
+        }
+    }
+
+    let mut pos = start;
+    dfs(&mut pos, plies, policy, &mut visited);
+
+    visited.keys().copied().collect()
+}
+
+/// start から policy に従う指し手で plies 手以内に到達できる局面を、手順ごとに f に渡す。
+/// 同一局面が複数回渡されうる。メモリ使用量は plies に比例する程度で済む。
+pub fn for_each_reachable(
+    start: &mut Position,
+    plies: u32,
+    policy: IllegalPolicy,
+    f: &mut impl FnMut(&Position),
+) {
+    f(start);
+
+    if plies == 0 || start.can_capture_king() {
+        return;
+    }
+    for mv in your_move::moves_with_policy(start, policy) {
+        position::with_move(start, &mv, |pos| {
+            for_each_reachable(pos, plies - 1, policy, f)
+        })
+        .unwrap();
+    }
+}
+
+//--------------------------------------------------------------------
+// 王手の受け
+//--------------------------------------------------------------------
+
+/// 手番側の玉に check_from から掛かっている王手に対し、合駒または王手駒を取る手のうち
+/// 最も安いものを (指し手, 費やす駒の価値) として返す。玉を動かす手は含まない。
+///
+/// 費やす駒の価値は動かす駒 (駒打ちなら打つ駒) の PRICES_0 による。
+/// 候補は your_move::moves_evasion() で生成し、自殺手は除く。
+/// 手番側の玉がないか、該当する手がなければ None を返す。
+pub fn best_interposition(pos: &mut Position, check_from: Sq) -> Option<(Move, u8)> {
+    let sq_king = ai::find_king_sq(pos.board(), pos.side())?;
+    let sqs_block = block_sqs(sq_king, check_from);
+
+    let mvs: Vec<_> = your_move::moves_evasion(pos)
+        .filter(|mv| sqs_block.contains(&mv.dst()))
+        .filter(|mv| !matches!(mv, Move::Nondrop(nondrop) if nondrop.src == sq_king))
+        .collect();
+
+    let cands: Vec<_> = mvs
+        .into_iter()
+        .map(|mv| {
+            let pt = match &mv {
+                Move::Nondrop(nondrop) => pos.board()[nondrop.src].piece().unwrap(),
+                Move::Drop(drop) => drop.pt,
+            };
+            (mv, PRICES_0[pt])
+        })
+        .collect();
+
+    cands
+        .into_iter()
+        .filter(|(mv, _)| position::with_move(pos, mv, |pos| !pos.can_capture_king()).unwrap())
+        .min_by_key(|(_, price)| *price)
+}
+
+/// 手番側の合法手 (自殺手、打ち歩詰めを除く) がちょうど 1 つならそれを返す。
+/// 自動再生の早送りや、棋譜への「一手」注記に使える。
+pub fn is_forced(pos: &mut Position) -> Option<Move> {
+    let mut mvs = your_move::moves_with_policy(pos, IllegalPolicy::Strict);
+    if mvs.len() == 1 {
+        mvs.pop()
+    } else {
+        None
+    }
+}
+
+/// 玉 sq_king と王手駒 check_from の間のマスたちと check_from を返す。
+/// 両者が直線上にない (桂の王手など) 場合は check_from のみ。
+fn block_sqs(sq_king: Sq, check_from: Sq) -> Vec<Sq> {
+    let dx = check_from.x().get() - sq_king.x().get();
+    let dy = check_from.y().get() - sq_king.y().get();
+    if !(dx == 0 || dy == 0 || dx.abs() == dy.abs()) {
+        return vec![check_from];
+    }
+
+    let step = Sq::from_xy(dx.signum(), dy.signum()).get();
+    let mut sqs = Vec::new();
+    let mut sq = sq_king;
+    while sq != check_from {
+        sq = Sq::new(sq.get() + step);
+        sqs.push(sq);
+    }
+    sqs
+}
+
+//--------------------------------------------------------------------
+// 駒の受け
+//--------------------------------------------------------------------
+
+/// 手番側が mv を指すことで、相手の利き数が自分の利き数を上回っていた自駒 (玉を除く) が
+/// そうでなくなる (逃げる、または紐を付ける) 場合、その駒の指す前のマスを返す。
+/// 該当する駒が複数あれば PRICES_0 による価値が最も高いもの (同価値ならマスの昇順で最初のもの)。
+/// 該当する駒がないか、mv を適用できない場合は None を返す。
+pub fn saves_piece(pos: &mut Position, mv: &Move) -> Option<Sq> {
+    let side = pos.side();
+
+    let is_endangered = |eff_board: &EffectBoard, sq: Sq| {
+        eff_board[sq][side.inv()].count() > eff_board[sq][side].count()
+    };
+
+    let eff_board = EffectBoard::from_board(pos.board(), side);
+    let mut sqs: Vec<_> = Sq::iter_valid()
+        .filter(|&sq| {
+            pos.board()[sq]
+                .piece_of(side)
+                .map(|pt| pt != Piece::King)
+                .unwrap_or(false)
+                && is_endangered(&eff_board, sq)
+        })
+        .collect();
+    if sqs.is_empty() {
+        return None;
+    }
+    sqs.sort_by_key(|&sq| std::cmp::Reverse(PRICES_0[pos.board()[sq].piece_of(side).unwrap()]));
+
+    position::with_move(pos, mv, |pos| {
+        let eff_board = EffectBoard::from_board(pos.board(), side);
+        sqs.into_iter().find(|&sq| {
+            // 動かした駒は移動先で判定する
+            let sq_after = match mv {
+                Move::Nondrop(nondrop) if nondrop.src == sq => nondrop.dst,
+                _ => sq,
+            };
+            !is_endangered(&eff_board, sq_after)
+        })
+    })
+    .ok()
+    .flatten()
+}
+
+//--------------------------------------------------------------------
+// 詰み探索
+//--------------------------------------------------------------------
+
+/// 「わたしの勝ち」宣言の分類
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WinClaim {
+    TrueMate(u32), // 宣言した手を含め、攻め方 n 手以内の本当の詰み
+    KingCapture,   // 王手は掛かっているが詰みとは限らない(次に玉を取れるというだけ)
+    Heuristic,     // 王手すら掛かっていない
+}
+
+/// classify_win_claim() で調べる詰み手数の上限 (攻め方の手数)
+pub const WIN_CLAIM_MAX_DEPTH: u32 = 3;
+
+/// 思考ルーチンの手番で、思考ルーチンが mv を指して勝ちを宣言したとき、それが本当の詰みかどうかを分類する。
+/// 原作の勝ち宣言は駒得評価と独自の詰み判定に基づくヒューリスティックなので、その検証用。
+///
+/// 詰み手数は、相手の各応手に対する solve_mate() の最大値に 1 を加えたものとなる。
+/// mv が適用できない場合は Heuristic を返す。
+pub fn classify_win_claim(ai: &Ai, mv: &Move) -> WinClaim {
+    let mut pos = ai.pos().clone();
+    let my = pos.side();
+
+    position::with_move(&mut pos, mv, |pos| {
+        if !pos.in_check(my.inv()) {
+            return WinClaim::Heuristic;
+        }
+
+        let replies: Vec<_> = your_move::moves_legal(pos).collect();
+        if replies.is_empty() {
+            // 打ち歩詰めは反則なので詰みとは認めない
+            return if mv.is_drop_pt(Piece::Pawn) {
+                WinClaim::KingCapture
+            } else {
+                WinClaim::TrueMate(1)
+            };
+        }
+
+        replies
+            .iter()
+            .try_fold(0, |acc, reply| {
+                position::with_move(pos, reply, |pos| solve_mate(pos, WIN_CLAIM_MAX_DEPTH - 1))
+                    .unwrap()
+                    .map(|n| acc.max(n))
+            })
+            .map_or(WinClaim::KingCapture, |n| WinClaim::TrueMate(n + 1))
+    })
+    .unwrap_or(WinClaim::Heuristic)
+}
+
+/// explain_non_mate() の結果
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MateExplanation {
+    Mate,          // 本当に詰んでいる
+    DropPawnMate,  // 詰んでいるが打ち歩詰め (反則)
+    Evasion(Move), // 相手にはこの回避手がある
+    NotCheck,      // 王手になっていない
+    Illegal,       // 疑似合法でないか、自殺手
+}
+
+/// 手番側が claimed_mate を指すと相手玉が詰むかを調べ、詰まないならその理由を返す。
+/// 詰まない場合、相手の合法手 (自殺手を除く) のうち最初に見つかったものを反例として返す。
+///
+/// 思考ルーチンの詰み判定 (judge_mate_your) と同じく、回避手が存在するかどうかで判定する。
+pub fn explain_non_mate(pos: &mut Position, claimed_mate: &Move) -> MateExplanation {
+    if !your_move::is_pseudo_legal(pos, claimed_mate) {
+        return MateExplanation::Illegal;
+    }
+
+    let side = pos.side();
+    let is_drop_pawn = claimed_mate.is_drop_pt(Piece::Pawn);
+
+    position::with_move(pos, claimed_mate, |pos| {
+        if pos.can_capture_king() {
+            return MateExplanation::Illegal;
+        }
+        if !pos.in_check(side.inv()) {
+            return MateExplanation::NotCheck;
+        }
+        match your_move::moves_legal(pos).next() {
+            Some(mv) => MateExplanation::Evasion(mv),
+            None if is_drop_pawn => MateExplanation::DropPawnMate,
+            None => MateExplanation::Mate,
+        }
+    })
+    .unwrap_or(MateExplanation::Illegal)
+}
+
+/// 手番側が攻め方 max_depth 手以内に相手玉を詰ませられるかを王手のみの探索で調べる。
+/// 詰ませられるなら最短の手数を返す。
+///
+/// 双方とも自殺手は指さないものとし、攻め方の打ち歩詰めは認めない。
+pub fn solve_mate(pos: &mut Position, max_depth: u32) -> Option<u32> {
+    (1..=max_depth).find(|&depth| can_mate_within(pos, depth))
+}
+
+/// 手番側が攻め方 depth 手以内に詰ませられるか。
+fn can_mate_within(pos: &mut Position, depth: u32) -> bool {
+    if depth == 0 {
+        return false;
+    }
+
+    let side = pos.side();
+    let mvs: Vec<_> = your_move::moves_legal(pos)
+        .filter(|mv| mv.gives_check(pos))
+        .collect();
+
+    mvs.into_iter().any(|mv| {
+        let is_drop_pawn = mv.is_drop_pt(Piece::Pawn);
+        position::with_move(pos, &mv, |pos| {
+            debug_assert!(pos.in_check(side.inv()));
+            if is_drop_pawn && your_move::moves_legal(pos).next().is_none() {
+                return false;
+            }
+            is_mated_within(pos, depth)
+        })
+        .unwrap()
+    })
+}
+
+/// 手番側 (王手を掛けられている側) が、攻め方の残り depth 手 (直前の王手を含む) 以内に詰まされるか。
+fn is_mated_within(pos: &mut Position, depth: u32) -> bool {
+    let mvs: Vec<_> = your_move::moves_legal(pos).collect();
+    if mvs.is_empty() {
+        return true;
+    }
+    if depth <= 1 {
+        return false;
+    }
+
+    mvs.into_iter()
+        .all(|mv| position::with_move(pos, &mv, |pos| can_mate_within(pos, depth - 1)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::your_player::YourPlayerLegal;
+
+    #[test]
+    fn test_cross_check_generators() {
+        for _ in 0..300 {
+            let mut pos = Position::random(&mut rand::thread_rng());
+            // 玉を取る手は適用できない
+            if pos.can_capture_king() {
+                continue;
+            }
+            cross_check_generators(&mut pos);
+        }
+    }
+
+    #[test]
+    fn test_explain_non_mate() {
+        // 頭金
+        let mut pos = Position::from_sfen("sfen 4k4/9/4P4/9/9/9/9/9/4K4 b GP 1").unwrap();
+        let mv = Move::drop(Piece::Gold, Sq::from_xy(5, 2));
+        assert_eq!(explain_non_mate(&mut pos, &mv), MateExplanation::Mate);
+
+        // 斜めからの金打ちは逃げられる
+        let mv = Move::drop(Piece::Gold, Sq::from_xy(4, 2));
+        match explain_non_mate(&mut pos, &mv) {
+            MateExplanation::Evasion(mv_evasion) => {
+                assert!(position::with_move(&mut pos, &mv, |pos| {
+                    your_move::moves_legal(pos).any(|mv| mv == mv_evasion)
+                })
+                .unwrap());
+            }
+            res => panic!("unexpected: {:?}", res),
+        }
+
+        // 王手でない
+        let mv = Move::drop(Piece::Gold, Sq::from_xy(5, 5));
+        assert_eq!(explain_non_mate(&mut pos, &mv), MateExplanation::NotCheck);
+
+        // 持っていない駒は打てない
+        let mv = Move::drop(Piece::Rook, Sq::from_xy(5, 2));
+        assert_eq!(explain_non_mate(&mut pos, &mv), MateExplanation::Illegal);
+
+        // 打ち歩詰め
+        let mut pos = Position::from_sfen("sfen 3lkl3/9/4G4/9/9/9/9/9/4K4 b P 1").unwrap();
+        let mv = Move::drop(Piece::Pawn, Sq::from_xy(5, 2));
+        assert_eq!(
+            explain_non_mate(&mut pos, &mv),
+            MateExplanation::DropPawnMate
+        );
+    }
+
+    #[test]
+    fn test_solve_mate() {
+        // 頭金の 1 手詰め
+        let mut pos = Position::from_sfen("sfen 4k4/9/4P4/9/9/9/9/9/4K4 b GP 1").unwrap();
+        assert_eq!(solve_mate(&mut pos, 3), Some(1));
+
+        // 打ち歩詰めは詰みと認めない
+        let mut pos = Position::from_sfen("sfen 3lkl3/9/4G4/9/9/9/9/9/4K4 b P 1").unwrap();
+        assert_eq!(solve_mate(&mut pos, 1), None);
+
+        // 飛車 1 枚では詰まない
+        let mut pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K4 b R 1").unwrap();
+        assert_eq!(solve_mate(&mut pos, 3), None);
+    }
+
+    #[test]
+    fn test_classify_win_claim() {
+        let pos = Position::from_sfen("sfen 4k4/9/4P4/9/9/9/9/9/4K4 b GP 1").unwrap();
+        let ai = Ai::from_position(pos, Side::Sente, false);
+        assert_eq!(
+            classify_win_claim(&ai, &Move::drop(Piece::Gold, Sq::from_xy(5, 2))),
+            WinClaim::TrueMate(1)
+        );
+        assert_eq!(
+            classify_win_claim(&ai, &Move::drop(Piece::Gold, Sq::from_xy(5, 5))),
+            WinClaim::Heuristic
+        );
+
+        // 打ち歩詰めは何手詰めとも扱わない
+        let pos = Position::from_sfen("sfen 3lkl3/9/4G4/9/9/9/9/9/4K4 b P 1").unwrap();
+        let ai = Ai::from_position(pos, Side::Sente, false);
+        assert_eq!(
+            classify_win_claim(&ai, &Move::drop(Piece::Pawn, Sq::from_xy(5, 2))),
+            WinClaim::KingCapture
+        );
+
+        // 王手だが詰まない
+        let pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K4 b R 1").unwrap();
+        let ai = Ai::from_position(pos, Side::Sente, false);
+        assert_eq!(
+            classify_win_claim(&ai, &Move::drop(Piece::Rook, Sq::from_xy(5, 5))),
+            WinClaim::KingCapture
+        );
+    }
+
+    /// 思考ルーチンの挙動が変わっていないことを確かめる。
+    /// 意図して挙動を変えた場合は deterministic_game() で testdata/golden/ を再生成すること。
+    #[test]
+    fn test_deterministic_game() {
+        const MAX_PLY: i32 = 200;
+
+        for &(handicap, seed, golden) in &[
+            (
+                Handicap::YourSente,
+                1,
+                include_str!("../testdata/golden/your_sente_1.txt"),
+            ),
+            (
+                Handicap::MySente,
+                2,
+                include_str!("../testdata/golden/my_sente_2.txt"),
+            ),
+            (
+                Handicap::YourHishaochi,
+                3,
+                include_str!("../testdata/golden/your_hishaochi_3.txt"),
+            ),
+            (
+                Handicap::MyNimaiochi,
+                4,
+                include_str!("../testdata/golden/my_nimaiochi_4.txt"),
+            ),
+        ] {
+            let record = deterministic_game(handicap, false, seed, MAX_PLY);
+            assert_eq!(record, golden.parse::<Record>().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_saves_piece() {
+        // 5五の銀に歩が当たっている
+        let mut pos = Position::from_sfen("sfen 4k4/9/9/4p4/4S4/9/9/9/4K4 b G 1").unwrap();
+        let sq_silver = Sq::from_xy(5, 5);
+
+        // 逃げる
+        let mv = Move::nondrop(sq_silver, Sq::from_xy(6, 6), false);
+        assert_eq!(saves_piece(&mut pos, &mv), Some(sq_silver));
+
+        // 紐を付ける
+        let mv = Move::drop(Piece::Gold, Sq::from_xy(5, 6));
+        assert_eq!(saves_piece(&mut pos, &mv), Some(sq_silver));
+
+        // 関係ない手
+        let mv = Move::nondrop(Sq::from_xy(5, 9), Sq::from_xy(4, 9), false);
+        assert_eq!(saves_piece(&mut pos, &mv), None);
+
+        // 当たりの駒がない
+        let mut pos = Position::from_sfen("sfen 4k4/9/9/9/4S4/9/9/9/4K4 b G 1").unwrap();
+        let mv = Move::nondrop(sq_silver, Sq::from_xy(6, 6), false);
+        assert_eq!(saves_piece(&mut pos, &mv), None);
+    }
+
+    #[test]
+    fn test_game_loop() {
+        for &handicap in &[
+            Handicap::YourSente,
+            Handicap::MySente,
+            Handicap::MyHishaochi,
+        ] {
+            let ai = Ai::new(handicap, false);
+            let mut game = GameLoop::new(ai, YourPlayerLegal::new()).with_max_ply(40);
+
+            // 手番は先手から交互に、手数は 1 ずつ増える
+            let events: Vec<_> = game.by_ref().collect();
+            assert!(!events.is_empty());
+            assert_eq!(events[0].0, 1);
+            assert_eq!(events[0].1, handicap.initial_pos().side());
+            for (i, (ply, side, _)) in events.iter().enumerate() {
+                assert_eq!(*ply, 1 + i as i32);
+                assert_eq!(
+                    *side,
+                    if i % 2 == 0 {
+                        events[0].1
+                    } else {
+                        events[0].1.inv()
+                    }
+                );
+            }
+
+            let end = game.end().unwrap();
+            if *end == GameEnd::MaxPly {
+                assert_eq!(events.len(), 40);
+            }
+            assert!(game.next().is_none());
+        }
+    }
+}
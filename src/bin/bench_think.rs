@@ -0,0 +1,63 @@
+//! 思考ルーチンのベンチマーク
+//!
+//! SFEN を 1 行に 1 つずつ書いたファイルを読み、各局面で手番側を思考ルーチンとして思考させる。
+
+use std::path::PathBuf;
+use std::time::Instant;
+
+use structopt::StructOpt;
+
+use naitou_clone::ai::Ai;
+use naitou_clone::log::ThinkStats;
+use naitou_clone::prelude::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(long)]
+    timelimit: bool,
+
+    #[structopt(long, default_value = "1")]
+    repeat: u32,
+
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let poss = std::fs::read_to_string(&opt.path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Position::from_sfen)
+        .collect::<Result<Vec<_>, _>>()?;
+    eyre::ensure!(!poss.is_empty(), "no positions");
+
+    let mut stats = ThinkStats::new();
+    let mut n_think: u64 = 0;
+
+    let start = Instant::now();
+    for _ in 0..opt.repeat {
+        for pos in &poss {
+            let mut ai = Ai::from_position(pos.clone(), pos.side(), opt.timelimit);
+            ai.think(&mut stats);
+            n_think += 1;
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!("positions: {}, repeat: {}", poss.len(), opt.repeat);
+    println!("elapsed: {:.3} s", elapsed);
+    println!("positions/sec: {:.1}", n_think as f64 / elapsed);
+    println!(
+        "candidates/position: {:.1}",
+        stats.n_cand as f64 / n_think as f64
+    );
+    println!(
+        "effect boards/position: {:.1}",
+        stats.n_eff_board as f64 / n_think as f64
+    );
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! SFEN の一括評価
+//!
+//! 標準入力から SFEN を 1 行に 1 つずつ読み、各局面で思考ルーチンに思考させる。
+//! 結果は TSV で標準出力に書く (1 行目はヘッダ)。
+//!
+//! 空行と '#' で始まる行は無視する。解釈できない行は標準エラー出力に報告して読み飛ばす。
+
+use std::io::{self, BufRead};
+
+use structopt::StructOpt;
+
+use naitou_clone::analysis;
+use naitou_clone::prelude::*;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 思考ルーチン側 (b/w/sente/gote)。局面の手番と異なる場合は手番を変更する。
+    /// 省略時は各局面の手番側。
+    #[structopt(long)]
+    side: Option<Side>,
+
+    #[structopt(long)]
+    timelimit: bool,
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    println!("sfen\tside\tresult\tadv_price\tdisadv_price\tpower_my\tpower_your");
+
+    for (lineno, line) in itertools::zip(1.., io::stdin().lock().lines()) {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut pos = match Position::from_sfen(line) {
+            Ok(pos) => pos,
+            Err(e) => {
+                eprintln!("line {}: {}", lineno, e);
+                continue;
+            }
+        };
+        if let Some(side) = opt.side {
+            *pos.side_mut() = side;
+            pos.refresh_zobrist();
+        }
+        let side = pos.side();
+
+        let (entry, root_eval) = analysis::evaluate(&pos, side, opt.timelimit);
+
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            line,
+            side,
+            entry,
+            root_eval.adv_price,
+            root_eval.disadv_price,
+            root_eval.power_my,
+            root_eval.power_your
+        );
+    }
+
+    Ok(())
+}
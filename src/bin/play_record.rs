@@ -188,57 +188,54 @@ fn play_my(ren: &mut RenderCtx, _entry: &RecordEntry) -> eyre::Result<()> {
     let mut break_flag = false;
 
     while !break_flag {
-        run_frame_hooked(ren, BTNS_NONE, &|addr| match addr {
-            emu::ADDR_YOUR_TURN => {
-                break_flag = true;
-            }
-            emu::ADDR_THINK => {
-                logger.log_progress(
-                    emu::get_progress_ply(),
-                    emu::get_progress_level(),
-                    emu::get_progress_level_sub(),
-                );
-                logger.log_book_state(emu::get_book_state());
-                logger.log_root_eff_board(emu::get_effect_board());
-            }
-            emu::ADDR_ROOT_EVALED => {
-                logger.log_root_eval(emu::get_root_eval());
-                logger.log_best_eval(emu::get_best_eval()); // デフォルト値
-            }
-            emu::ADDR_TRY_IMPROVE_BEST => {
-                logger.start_cand(emu::get_cand_move());
-                logger.log_cand_eff_board(emu::get_effect_board());
-                logger.log_cand_pos_eval(emu::get_position_eval());
-                logger.log_cand_eval(emu::get_cand_eval());
-            }
-            emu::ADDR_IMPROVE_BEST => {
-                logger.log_cand_improve();
-            }
-            emu::ADDR_TRY_IMPROVE_BEST_DONE_NONDROP | emu::ADDR_TRY_IMPROVE_BEST_DONE_DROP => {
-                logger.end_cand();
-            }
-            emu::ADDR_THINK_DONE => {
-                logger.log_best_eval(emu::get_best_eval());
-            }
-            emu::ADDR_YOUR_SUICIDE => {
-                logger.log_record_entry(RecordEntry::YourSuicide);
-                break_flag = true;
-            }
-            emu::ADDR_YOUR_WIN => {
-                logger.log_record_entry(RecordEntry::YourWin);
-                break_flag = true;
-            }
-            emu::ADDR_MOVE_MY => {
-                logger.log_record_entry(RecordEntry::Move(emu::get_my_move()));
-            }
-            emu::ADDR_MY_WIN => {
-                logger.log_record_entry(RecordEntry::MyWin(emu::get_my_move()));
+        run_frame_hooked(ren, BTNS_NONE, &|addr| {
+            // 終局処理に達したら、画面上の結果表示で止める
+            if let Some(phase) = emu::GamePhase::from_hook_addr(addr) {
+                logger.log_record_entry(phase.record_entry().unwrap());
                 break_flag = true;
+                return;
             }
-            addr if emu::ADDRS_TWEAK.contains(&addr) => {
-                logger.log_cand_eval(emu::get_cand_eval());
+
+            match addr {
+                emu::ADDR_YOUR_TURN => {
+                    break_flag = true;
+                }
+                emu::ADDR_THINK => {
+                    logger.log_progress(
+                        emu::get_progress_ply(),
+                        emu::get_progress_level(),
+                        emu::get_progress_level_sub(),
+                    );
+                    logger.log_book_state(emu::get_book_state());
+                    logger.log_root_eff_board(emu::get_effect_board());
+                }
+                emu::ADDR_ROOT_EVALED => {
+                    logger.log_root_eval(emu::get_root_eval());
+                    logger.log_best_eval(emu::get_best_eval()); // デフォルト値
+                }
+                emu::ADDR_TRY_IMPROVE_BEST => {
+                    logger.start_cand(emu::get_cand_move());
+                    logger.log_cand_eff_board(emu::get_effect_board());
+                    logger.log_cand_pos_eval(emu::get_position_eval());
+                    logger.log_cand_eval(emu::get_cand_eval());
+                }
+                emu::ADDR_IMPROVE_BEST => {
+                    logger.log_cand_improve();
+                }
+                emu::ADDR_TRY_IMPROVE_BEST_DONE_NONDROP | emu::ADDR_TRY_IMPROVE_BEST_DONE_DROP => {
+                    logger.end_cand();
+                }
+                emu::ADDR_THINK_DONE => {
+                    logger.log_best_eval(emu::get_best_eval());
+                }
+                emu::ADDR_MOVE_MY => {
+                    logger.log_record_entry(RecordEntry::Move(emu::get_my_move()));
+                }
+                addr if emu::ADDRS_TWEAK.contains(&addr) => {
+                    logger.log_cand_eval(emu::get_cand_eval());
+                }
+                _ => {}
             }
-            _ => {}
         })?;
     }
 
@@ -293,7 +290,7 @@ fn main() -> eyre::Result<()> {
     let opt = Opt::from_args();
 
     fceux::init(opt.path_rom)?;
-    let record = Record::from_file(opt.path_record)?;
+    let record = Record::from_file_validated(opt.path_record, false)?;
 
     let sdl = sdl2::init().map_err(|s| eyre!(s))?;
     let sdl_video = sdl.video().map_err(|s| eyre!(s))?;
@@ -1,13 +1,30 @@
 //! your_move テスト用
 
+use structopt::StructOpt;
+
 use naitou_clone::usi_random;
+use naitou_clone::your_move::IllegalPolicy;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    /// 原作通り、自殺手と打ち歩詰めも指す
+    #[structopt(long)]
+    faithful: bool,
+}
 
 fn main() -> eyre::Result<()> {
     if cfg!(debug_assertions) {
         std::env::set_var("RUST_BACKTRACE", "1");
     }
 
-    usi_random::interact()?;
+    let opt = Opt::from_args();
+
+    let policy = if opt.faithful {
+        IllegalPolicy::Faithful
+    } else {
+        IllegalPolicy::Strict
+    };
+    usi_random::interact_with_policy(policy)?;
 
     Ok(())
 }
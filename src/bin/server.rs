@@ -0,0 +1,98 @@
+//! 局面解析用の HTTP サーバ
+//!
+//! POST /analyze に {"sfen": "sfen ...", "side": "b" | "w"} を送ると、
+//! side 側を思考ルーチン側として思考した結果を JSON で返す。
+
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Response, Server};
+
+use naitou_clone::ai::RootEval;
+use naitou_clone::analysis;
+use naitou_clone::prelude::*;
+use naitou_clone::record::RecordEntry;
+use naitou_clone::sfen;
+
+#[derive(Debug, StructOpt)]
+struct Opt {
+    #[structopt(long, default_value = "127.0.0.1:8000")]
+    addr: String,
+
+    #[structopt(long)]
+    timelimit: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnalyzeRequest {
+    sfen: String,
+    side: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AnalyzeResponse {
+    entry: &'static str,
+    #[serde(rename = "move")]
+    mv: Option<String>,
+    root_eval: RootEval,
+}
+
+fn analyze(body: &str, timelimit: bool) -> eyre::Result<AnalyzeResponse> {
+    let req: AnalyzeRequest = serde_json::from_str(body)?;
+
+    let pos = Position::from_sfen(&req.sfen)?;
+    let side = sfen::sfen_to_side(&req.side)?;
+    eyre::ensure!(pos.side() == side, "side to move must be {}", req.side);
+    // 思考ルーチンは双方の玉が盤上にあることを前提としている
+    eyre::ensure!(
+        Side::iter().all(|side| pos.king(side).is_some()),
+        "both kings must be on board"
+    );
+
+    let (entry, root_eval) = analysis::evaluate(&pos, side, timelimit);
+    let (entry, mv) = match entry {
+        RecordEntry::Move(mv) => ("Move", Some(mv)),
+        RecordEntry::MyWin(mv) => ("MyWin", Some(mv)),
+        RecordEntry::YourSuicide => ("YourSuicide", None),
+        RecordEntry::YourWin => ("YourWin", None),
+    };
+
+    Ok(AnalyzeResponse {
+        entry,
+        mv: mv.map(|mv| sfen::move_to_sfen(&mv).into_owned()),
+        root_eval,
+    })
+}
+
+fn main() -> eyre::Result<()> {
+    let opt = Opt::from_args();
+
+    let server = Server::http(&opt.addr).map_err(|e| eyre::eyre!(e))?;
+    let header_json = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+
+    // 個々のリクエストでのエラーはサーバを止めず、ログに出して次へ進む
+    for mut request in server.incoming_requests() {
+        let response = if !(request.method() == &Method::Post && request.url() == "/analyze") {
+            Response::from_string("not found").with_status_code(404)
+        } else {
+            let mut body = String::new();
+            match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => match analyze(&body, opt.timelimit) {
+                    Ok(res) => match serde_json::to_string(&res) {
+                        Ok(json) => Response::from_string(json).with_header(header_json.clone()),
+                        Err(e) => Response::from_string(e.to_string()).with_status_code(500),
+                    },
+                    Err(e) => Response::from_string(e.to_string()).with_status_code(400),
+                },
+                Err(e) => {
+                    Response::from_string(format!("invalid body: {}", e)).with_status_code(400)
+                }
+            }
+        };
+
+        if let Err(e) = request.respond(response) {
+            eprintln!("failed to respond: {}", e);
+        }
+    }
+
+    Ok(())
+}
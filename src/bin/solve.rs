@@ -6,7 +6,7 @@ use rayon::prelude::*;
 use structopt::StructOpt;
 
 use naitou_clone::ai::Ai;
-use naitou_clone::log::NullLogger;
+use naitou_clone::log::{Logger, NullLogger};
 use naitou_clone::prelude::*;
 use naitou_clone::record::RecordEntry;
 use naitou_clone::sfen;
@@ -17,6 +17,20 @@ struct Opt {
     #[structopt(long)]
     timelimit: bool,
 
+    /// 各解について、思考ルーチンの各手番でのルート局面評価も出力する
+    #[structopt(long)]
+    annotate: bool,
+
+    /// 開始局面 (SFEN)。省略時は手合割の初期局面。
+    /// 思考ルーチン側は手合割により決まり、進行度は手数から推定する。
+    #[structopt(long)]
+    sfen: Option<String>,
+
+    /// 開始局面から探索前に指す手 (USI 形式、空白区切り)。
+    /// 思考ルーチン側の手は思考ルーチン自身の指し手と一致しなければならない。
+    #[structopt(long)]
+    moves: Option<String>,
+
     #[structopt()]
     handicap: Handicap,
 
@@ -25,6 +39,10 @@ struct Opt {
 }
 
 /// ai は your 側の手番と仮定している。
+///
+/// your 側の指し手には your_move::moves_pseudo_legal() を用いる。
+/// これは成り/不成の両方を含む(原作で your 側が指せる手の集合と一致する)ので、
+/// 不成の手順も探索対象になっている。
 fn rec(sols: &mut Vec<Vec<Move>>, ai: &mut Ai, history: &mut Vec<Move>, depth: i32) {
     if depth <= 0 {
         return;
@@ -57,41 +75,88 @@ fn rec(sols: &mut Vec<Vec<Move>>, ai: &mut Ai, history: &mut Vec<Move>, depth: i
     }
 }
 
-fn step(ai: &mut Ai, history: &mut Vec<Move>, mv_your: &Move) {
-    ai.move_your(mv_your);
+/// your 側の指し手 mv_your と、それに対する思考ルーチンの指し手 (あれば) を進める。
+fn step(ai: &mut Ai, history: &mut Vec<Move>, mv_your: &Move) -> RecordEntry {
     history.push(mv_your.clone());
 
-    let (entry, _) = ai.step_my(&mut NullLogger::new());
-    match entry {
-        RecordEntry::Move(mv_my) => {
-            history.push(mv_my);
-        }
-        _ => panic!("unexpected: {}", entry),
+    let (entry, _) = ai.play(Some(mv_your.clone()));
+    if let RecordEntry::Move(mv_my) = &entry {
+        history.push(mv_my.clone());
     }
+
+    entry
 }
 
 fn solve(mut ai: Ai, mut history: Vec<Move>, mv_your: &Move, depth: i32) -> Vec<Vec<Move>> {
-    step(&mut ai, &mut history, mv_your);
-
     let mut sols = Vec::new();
-    rec(&mut sols, &mut ai, &mut history, depth - 1);
+
+    // 任意の局面から始めた場合、初手で終局することもある
+    match step(&mut ai, &mut history, mv_your) {
+        RecordEntry::Move(_) => rec(&mut sols, &mut ai, &mut history, depth - 1),
+        RecordEntry::YourWin => sols.push(history),
+        RecordEntry::MyWin(_) | RecordEntry::YourSuicide => {}
+    }
 
     sols
 }
 
+/// 解 sol を開始局面 ai から再生し、思考ルーチンの各手番について (手数, 思考結果, ルート局面評価) を出力する。
+/// 最後の your 指し手の後の思考 (投了) も含む。
+fn print_annotation(mut ai: Ai, sol: &[Move]) {
+    fn think(ai: &mut Ai) {
+        let ply = ai.pos().ply();
+        let mut logger = Logger::new();
+        ai.step_my(&mut logger);
+        let log = logger.into_log();
+        println!("  {}: {} {:?}", ply, log.record_entry, log.root_eval);
+    }
+
+    for mv in sol {
+        if ai.is_my_turn() {
+            think(&mut ai);
+        } else {
+            ai.move_your(mv);
+        }
+    }
+    think(&mut ai);
+}
+
 fn main() -> eyre::Result<()> {
     let opt = Opt::from_args();
 
-    let mut ai = Ai::new(opt.handicap, opt.timelimit);
+    let mut ai = match &opt.sfen {
+        Some(sfen) => {
+            let pos = Position::from_sfen(sfen)?;
+            Ai::from_position_seeded(pos, opt.handicap.my(), opt.timelimit)
+        }
+        None => Ai::new(opt.handicap, opt.timelimit),
+    };
+
     let mut history = Vec::new();
+    if let Some(moves) = &opt.moves {
+        for s in moves.split_ascii_whitespace() {
+            let mv = Move::from_sfen(s)?;
+            if ai.is_your_turn() && !your_move::moves_pseudo_legal(ai.pos()).any(|m| m == mv) {
+                eyre::bail!("illegal your move: {}", s);
+            }
+            ai.replay_move(&mv)?;
+            history.push(mv);
+        }
+    }
+    if ai.is_lost() {
+        eyre::bail!("my king is already capturable");
+    }
 
+    // 探索は your 側の手番から始める
+    let ai_start = ai.clone();
+    let n_prefix = history.len();
     if ai.is_my_turn() {
-        let (entry, _) = ai.step_my(&mut NullLogger::new());
+        let (entry, _) = ai.play(None);
         match entry {
             RecordEntry::Move(mv_my) => {
                 history.push(mv_my);
             }
-            _ => panic!("unexpected: {}", entry),
+            _ => eyre::bail!("game is already over: {}", entry),
         }
     }
 
@@ -112,6 +177,9 @@ fn main() -> eyre::Result<()> {
 
     for sol in sols {
         println!("{}", sol.iter().map(|mv| sfen::move_to_sfen(mv)).join(" "));
+        if opt.annotate {
+            print_annotation(ai_start.clone(), &sol[n_prefix..]);
+        }
     }
 
     Ok(())
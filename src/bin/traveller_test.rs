@@ -52,7 +52,7 @@ fn main() -> eyre::Result<()> {
     emu::run_frames(100, BTNS_NONE);
 
     let cursor = emu::get_cursor();
-    assert_eq!(cursor, Cursor::board(Sq::from_xy(5, 5)));
+    assert_eq!(cursor, emu::initial_cursor());
 
     let snap = fceux::snapshot_create();
     fceux::snapshot_save(&snap)?;
@@ -6,12 +6,13 @@ use chrono::prelude::*;
 use structopt::StructOpt;
 
 use naitou_clone::ai::Ai;
-use naitou_clone::emu::{self, BTNS_NONE};
+use naitou_clone::emu::{self, Cursor, BTNS_NONE};
 use naitou_clone::log::{Log, Logger, LoggerTrait};
 use naitou_clone::prelude::*;
 use naitou_clone::record::{Record, RecordEntry};
 use naitou_clone::your_player::{
-    YourPlayer, YourPlayerLegal, YourPlayerPseudoLegal, YourPlayerRecord,
+    YourPlayer, YourPlayerDefensive, YourPlayerGreedy, YourPlayerLegal, YourPlayerPseudoLegal,
+    YourPlayerRecord,
 };
 
 const DIR_LOG: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/log");
@@ -39,6 +40,18 @@ enum Cmd {
         #[structopt()]
         handicap: Handicap,
     },
+    Greedy {
+        #[structopt(long)]
+        timelimit: bool,
+        #[structopt()]
+        handicap: Handicap,
+    },
+    Defensive {
+        #[structopt(long)]
+        timelimit: bool,
+        #[structopt()]
+        handicap: Handicap,
+    },
     Record {
         #[structopt(parse(from_os_str))]
         path: PathBuf,
@@ -72,6 +85,8 @@ fn verify<P: YourPlayer>(handicap: Handicap, timelimit: bool, mut player: P) ->
     let mut record = Record::new(handicap, timelimit);
     let mut logs_ai = Vec::new();
     let mut logs_emu = Vec::new();
+    // your 側の初手のみ、カーソル位置をエミュレータから読まずに initial_cursor() とする
+    let mut cursor_first = Some(emu::initial_cursor());
 
     loop {
         let mut pos = ai.pos().clone();
@@ -104,7 +119,12 @@ fn verify<P: YourPlayer>(handicap: Handicap, timelimit: bool, mut player: P) ->
         };
 
         let log_ai = step_ai(&mut ai, &mv_your);
-        let log_emu = step_emu(&mv_your, my);
+        let cursor = if mv_your.is_some() {
+            cursor_first.take()
+        } else {
+            None
+        };
+        let log_emu = step_emu(&mv_your, my, cursor);
         let ok = log_ai == log_emu;
         let entry = log_ai.record_entry.clone();
 
@@ -130,85 +150,77 @@ fn verify<P: YourPlayer>(handicap: Handicap, timelimit: bool, mut player: P) ->
 }
 
 fn step_ai(ai: &mut Ai, mv_your: &Option<Move>) -> Log {
-    if let Some(mv) = mv_your {
-        ai.move_your(&mv);
-    }
-
     let mut logger = Logger::new();
-    let record_entry = ai.think(&mut logger);
-    match record_entry {
-        RecordEntry::Move(mv) => {
-            ai.move_my(&mv);
-        }
-        RecordEntry::MyWin(mv) => {
-            ai.move_my(&mv);
-        }
-        _ => {}
-    }
+    ai.play_with_logger(mv_your.clone(), &mut logger);
 
     logger.into_log()
 }
 
-fn step_emu(mv_your: &Option<Move>, my: Side) -> Log {
+/// cursor が Some なら、your 側の指し手をそのカーソル位置から入力する。
+fn step_emu(mv_your: &Option<Move>, my: Side, cursor: Option<Cursor>) -> Log {
     if let Some(mv) = mv_your {
-        emu::move_your(&mv, my.inv());
+        match cursor {
+            Some(cursor) => {
+                // initial_cursor() の仮定を実機と照合する
+                assert_eq!(emu::get_cursor(), cursor, "unexpected initial cursor");
+                emu::move_your_from(&cursor, mv, my.inv());
+            }
+            None => emu::move_your(mv, my.inv()),
+        }
     }
 
     let mut logger = Logger::new();
     let mut break_flag = false;
 
     while !break_flag {
-        emu::run_frame_hooked(BTNS_NONE, &|addr: u16| match addr {
-            emu::ADDR_YOUR_TURN => {
+        emu::run_frame_hooked(BTNS_NONE, &|addr: u16| {
+            // 終局処理に達したら、画面上の結果表示で止める
+            if let Some(phase) = emu::GamePhase::from_hook_addr(addr) {
+                logger.log_record_entry(phase.record_entry().unwrap());
                 break_flag = true;
+                return;
             }
-            emu::ADDR_THINK => {
-                logger.log_progress(
-                    emu::get_progress_ply(),
-                    emu::get_progress_level(),
-                    emu::get_progress_level_sub(),
-                );
-                logger.log_book_state(emu::get_book_state());
-                logger.log_root_eff_board(emu::get_effect_board());
-            }
-            emu::ADDR_ROOT_EVALED => {
-                logger.log_root_eval(emu::get_root_eval());
-                logger.log_best_eval(emu::get_best_eval()); // デフォルト値
-            }
-            emu::ADDR_TRY_IMPROVE_BEST => {
-                logger.start_cand(emu::get_cand_move());
-                logger.log_cand_eff_board(emu::get_effect_board());
-                logger.log_cand_pos_eval(emu::get_position_eval());
-                logger.log_cand_eval(emu::get_cand_eval());
-            }
-            emu::ADDR_IMPROVE_BEST => {
-                logger.log_cand_improve();
-            }
-            emu::ADDR_TRY_IMPROVE_BEST_DONE_NONDROP | emu::ADDR_TRY_IMPROVE_BEST_DONE_DROP => {
-                logger.end_cand();
-            }
-            emu::ADDR_THINK_DONE => {
-                logger.log_best_eval(emu::get_best_eval());
-            }
-            emu::ADDR_YOUR_SUICIDE => {
-                logger.log_record_entry(RecordEntry::YourSuicide);
-                break_flag = true;
-            }
-            emu::ADDR_YOUR_WIN => {
-                logger.log_record_entry(RecordEntry::YourWin);
-                break_flag = true;
-            }
-            emu::ADDR_MOVE_MY => {
-                logger.log_record_entry(RecordEntry::Move(emu::get_my_move()));
-            }
-            emu::ADDR_MY_WIN => {
-                logger.log_record_entry(RecordEntry::MyWin(emu::get_my_move()));
-                break_flag = true;
-            }
-            addr if emu::ADDRS_TWEAK.contains(&addr) => {
-                logger.log_cand_eval(emu::get_cand_eval());
+
+            match addr {
+                emu::ADDR_YOUR_TURN => {
+                    break_flag = true;
+                }
+                emu::ADDR_THINK => {
+                    logger.log_progress(
+                        emu::get_progress_ply(),
+                        emu::get_progress_level(),
+                        emu::get_progress_level_sub(),
+                    );
+                    logger.log_book_state(emu::get_book_state());
+                    logger.log_root_eff_board(emu::get_effect_board());
+                }
+                emu::ADDR_ROOT_EVALED => {
+                    logger.log_root_eval(emu::get_root_eval());
+                    logger.log_best_eval(emu::get_best_eval()); // デフォルト値
+                }
+                emu::ADDR_TRY_IMPROVE_BEST => {
+                    logger.start_cand(emu::get_cand_move());
+                    logger.log_cand_eff_board(emu::get_effect_board());
+                    logger.log_cand_pos_eval(emu::get_position_eval());
+                    logger.log_cand_eval(emu::get_cand_eval());
+                }
+                emu::ADDR_IMPROVE_BEST => {
+                    logger.log_cand_improve();
+                }
+                emu::ADDR_TRY_IMPROVE_BEST_DONE_NONDROP | emu::ADDR_TRY_IMPROVE_BEST_DONE_DROP => {
+                    logger.end_cand();
+                }
+                emu::ADDR_THINK_DONE => {
+                    logger.log_best_eval(emu::get_best_eval());
+                }
+                emu::ADDR_MOVE_MY => {
+                    logger.log_record_entry(RecordEntry::Move(emu::get_my_move()));
+                }
+                addr if emu::ADDRS_TWEAK.contains(&addr) => {
+                    logger.log_cand_eval(emu::get_cand_eval());
+                }
+                _ => {}
             }
-            _ => {}
         });
     }
 
@@ -329,8 +341,24 @@ fn main() -> eyre::Result<()> {
             cmd_nonrecord(handicap, timelimit, player)?;
         }
 
+        Cmd::Greedy {
+            handicap,
+            timelimit,
+        } => {
+            let player = YourPlayerGreedy::new();
+            cmd_nonrecord(handicap, timelimit, player)?;
+        }
+
+        Cmd::Defensive {
+            handicap,
+            timelimit,
+        } => {
+            let player = YourPlayerDefensive::new();
+            cmd_nonrecord(handicap, timelimit, player)?;
+        }
+
         Cmd::Record { path } => {
-            let record = Record::from_file(&path)?;
+            let record = Record::from_file_validated(&path, false)?;
             let handicap = record.handicap();
             let timelimit = record.timelimit();
             let player = YourPlayerRecord::new(record);
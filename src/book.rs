@@ -40,6 +40,7 @@ const fn bit_assign(x: u32, bit: usize, value: bool) -> u32 {
 
 /// 戦型
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Formation {
     Nakabisha,
     Sikenbisha,
@@ -52,6 +53,23 @@ pub enum Formation {
     Nothing,
 }
 
+impl std::fmt::Display for Formation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Self::Nakabisha => "中飛車",
+            Self::Sikenbisha => "四間飛車",
+            Self::Kakugawari => "角換わり",
+            Self::Sujichigai => "筋違い角",
+            Self::YourHishaochi => "your 飛車落ち",
+            Self::YourNimaiochi => "your 二枚落ち",
+            Self::MyHishaochi => "my 飛車落ち",
+            Self::MyNimaiochi => "my 二枚落ち",
+            Self::Nothing => "なし",
+        };
+        f.write_str(s)
+    }
+}
+
 impl Formation {
     pub fn from_handicap(handicap: Handicap, timelimit: bool) -> Self {
         match handicap {
@@ -141,6 +159,7 @@ fn book_sq(sq: Sq, my: Side) -> Sq {
 
 /// 定跡処理用状態データ
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BookState {
     pub formation: Formation,
     pub done_branch: u32, // 定跡分岐エントリ処理済みフラグ (size: 16)
@@ -160,6 +179,13 @@ impl BookState {
         self.formation
     }
 
+    /// 状態を変更せずに process() を試し、(定跡手, 処理後の戦型) を返す。
+    pub fn peek(&self, pos: &Position, progress_ply: u8) -> (Option<Move>, Formation) {
+        let mut this = self.clone();
+        let mv = this.process(pos, progress_ply);
+        (mv, this.formation)
+    }
+
     fn change_formation(&mut self, formation: Formation) {
         self.formation = formation;
         self.done_branch = 0;
@@ -227,6 +253,121 @@ impl BookState {
     }
 }
 
+/// 局面 pos がどの戦型に相当するかを推定する。
+///
+/// 平手の場合、my 側の飛車が5筋にあれば中飛車、そうでなければ四間飛車を起点とし、
+/// 定跡分岐の戦型変更エントリの条件を満たす限り戦型を変更していく。
+/// 手数の条件は pos の手数から求めた進行度で判定する。
+/// 中飛車/四間飛車のまま飛車が5筋/6筋にない場合は Formation::Nothing を返す。
+pub fn detect_formation(pos: &Position, handicap: Handicap) -> Formation {
+    let my = handicap.my();
+    let your = my.inv();
+    let progress_ply = num_traits::clamp(pos.ply() - 1, 0, 100) as u8;
+
+    let rook_x = Sq::iter_valid()
+        .find(|&sq| {
+            pos.board()[sq].is_side_pt(my, Piece::Rook)
+                || pos.board()[sq].is_side_pt(my, Piece::Dragon)
+        })
+        .map(|sq| book_sq(sq, my).x().get());
+
+    let mut formation = match handicap {
+        Handicap::YourSente | Handicap::MySente => {
+            if rook_x == Some(5) {
+                Formation::Nakabisha
+            } else {
+                Formation::Sikenbisha
+            }
+        }
+        _ => Formation::from_handicap(handicap, false),
+    };
+
+    'outer: loop {
+        for e in get_book_branch(formation) {
+            if let BookBranchEntry::Change(bra_ch) = e {
+                let sq_your = book_sq(bra_ch.sq_your, my);
+                if pos.board()[sq_your].is_side_pt(your, bra_ch.pt_your)
+                    && progress_ply <= bra_ch.ply
+                {
+                    formation = bra_ch.formation;
+                    continue 'outer;
+                }
+            }
+        }
+        break;
+    }
+
+    match formation {
+        Formation::Nakabisha | Formation::Sikenbisha if !matches!(rook_x, Some(4) | Some(5)) => {
+            Formation::Nothing
+        }
+        _ => formation,
+    }
+}
+
+/// 戦型 formation の定跡を Graphviz の DOT 形式で出力する。
+///
+/// 定跡分岐の応手指示はその条件をラベルとする辺で、戦型変更指示は変更先の戦型への破線の辺で表す。
+/// 定跡手順は順に辺で結ぶ。座標は定跡データと同じく my 側を後手としたもの。
+/// Formation::Nothing に対しては空のグラフを返す。
+pub fn to_dot(formation: Formation) -> String {
+    use std::fmt::Write;
+
+    let mut res = String::new();
+
+    writeln!(res, "digraph \"{}\" {{", formation).unwrap();
+
+    if formation != Formation::Nothing {
+        writeln!(res, "    start [label=\"{}\", shape=box];", formation).unwrap();
+
+        for (i, e) in get_book_branch(formation).iter().enumerate() {
+            match e {
+                BookBranchEntry::Move(bra_mv) => {
+                    let mv = Move::nondrop(bra_mv.src_my, bra_mv.dst_my, false);
+                    writeln!(res, "    branch{} [label=\"{}\"];", i, mv.pretty()).unwrap();
+                    writeln!(
+                        res,
+                        "    start -> branch{} [label=\"{}{}\"];",
+                        i,
+                        bra_mv.sq_your.pretty(),
+                        bra_mv.pt_your.pretty()
+                    )
+                    .unwrap();
+                }
+                BookBranchEntry::Change(bra_ch) => {
+                    writeln!(
+                        res,
+                        "    change{} [label=\"{}\", shape=box];",
+                        i, bra_ch.formation
+                    )
+                    .unwrap();
+                    writeln!(
+                        res,
+                        "    start -> change{} [label=\"{}{} (ply <= {})\", style=dashed];",
+                        i,
+                        bra_ch.sq_your.pretty(),
+                        bra_ch.pt_your.pretty(),
+                        bra_ch.ply
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        let mut prev = "start".to_owned();
+        for (i, e) in get_book_moves(formation).iter().enumerate() {
+            let mv = Move::nondrop(e.src_my, e.dst_my, false);
+            writeln!(res, "    moves{} [label=\"{}\"];", i, mv.pretty()).unwrap();
+            writeln!(res, "    {} -> moves{};", prev, i).unwrap();
+            prev = format!("moves{}", i);
+        }
+    }
+
+    writeln!(res, "}}").unwrap();
+
+    res
+}
+
 fn get_book_branch(formation: Formation) -> &'static [BookBranchEntry] {
     match formation {
         Formation::Nakabisha => BRANCH_NAKABISHA,
@@ -12,9 +12,11 @@
 //! 駒の利きの列挙順は思考ルーチンの挙動に影響することに注意。
 //!===================================================================
 
+use crate::position;
 use crate::prelude::*;
-use crate::price::PRICES_0;
+use crate::price::{PRICES_0, PRICES_1};
 use crate::util;
+use crate::Result;
 
 //--------------------------------------------------------------------
 // 駒の利き
@@ -62,16 +64,88 @@ pub fn piece_effects_ranged(side: Side, pt: Piece) -> impl Iterator<Item = i32>
 pub fn iter_effects(board: &Board, side: Side) -> impl Iterator<Item = (Sq, Sq)> + '_ {
     Sq::iter_valid()
         .filter_map(move |src| {
-            board[src]
-                .piece_of(side)
-                .map(move |pt| iter_effects_by(board, side, src, pt).map(move |dst| (src, dst)))
+            board[src].piece_of(side).map(move |pt| {
+                iter_effects_by_valid(board, side, src, pt).map(move |dst| (src, dst))
+            })
         })
         .flatten()
 }
 
+/// board 上で dst に利いている side 側の駒 pt の位置を列挙する。
+pub fn reachable_from(
+    board: &Board,
+    side: Side,
+    dst: Sq,
+    pt: Piece,
+) -> impl Iterator<Item = Sq> + '_ {
+    Sq::iter_valid().filter(move |&src| {
+        board[src].is_side_pt(side, pt)
+            && iter_effects_by_valid(board, side, src, pt).any(|sq| sq == dst)
+    })
+}
+
+/// board 上の side 側の駒 pt が、他の駒が動かないとして from から何手かかけて to に到達できるかどうかを返す。
+/// 途中のマスは空白でなければならず、to は空白または相手駒のマスでなければならない。
+/// 成りは考慮しない。reachable_from() と異なり、from に実際に駒があるかどうかは気にしない。
+///
+/// from が valid でなければ panic する。
+pub fn can_reach_eventually(board: &Board, side: Side, from: Sq, pt: Piece, to: Sq) -> bool {
+    assert!(from.is_valid(), "invalid square: {:?}", from);
+    if from == to {
+        return true;
+    }
+    let cell_to = board[to];
+    if !(cell_to.is_empty() || cell_to.is_side(side.inv())) {
+        return false;
+    }
+
+    // 駒自身が移動経路を塞がないよう、from を空白にした盤面で探索する
+    let mut board = board.clone();
+    board[from] = BoardCell::Empty;
+
+    let mut visited = [false; 11 * 11];
+    visited[from.get() as usize] = true;
+    let mut queue = std::collections::VecDeque::from(vec![from]);
+
+    while let Some(src) = queue.pop_front() {
+        // キューに入るのは from と空白マスのみなので src は valid
+        for dst in iter_effects_by_valid(&board, side, src, pt) {
+            if dst == to {
+                return true;
+            }
+            if !board[dst].is_empty() || visited[dst.get() as usize] {
+                continue;
+            }
+            visited[dst.get() as usize] = true;
+            queue.push_back(dst);
+        }
+    }
+
+    false
+}
+
 /// board 上の駒 (side, sq, pt) による利きを列挙する。
 /// 実際にこの駒があるかどうかは気にしない。
-fn iter_effects_by(board: &Board, side: Side, sq: Sq, pt: Piece) -> impl Iterator<Item = Sq> + '_ {
+///
+/// sq が valid でなければ panic する。
+pub fn iter_effects_by(
+    board: &Board,
+    side: Side,
+    sq: Sq,
+    pt: Piece,
+) -> impl Iterator<Item = Sq> + '_ {
+    assert!(sq.is_valid(), "invalid square: {:?}", sq);
+    iter_effects_by_valid(board, side, sq, pt)
+}
+
+/// iter_effects_by() と同じだが、sq が valid かどうかを検査しない。
+/// 遠隔利きの走査は ray_cell() を用いるため、呼び出し側は sq が valid であることを保証しなければならない。
+fn iter_effects_by_valid(
+    board: &Board,
+    side: Side,
+    sq: Sq,
+    pt: Piece,
+) -> impl Iterator<Item = Sq> + '_ {
     itertools::chain(
         iter_melee_effects_by(side, sq, pt),
         iter_ranged_effects_by(board, side, sq, pt),
@@ -99,6 +173,23 @@ fn iter_ranged_effects_by(
         .flat_map(move |dir| iter_uni_ranged_effects_by(board, sq, dir))
 }
 
+/// 遠隔利きの走査用に board[sq] を返す。
+/// unsafe-fast feature 有効時は境界チェックを省く。
+///
+/// 呼び出し側は sq が ok であることを保証しなければならない。
+/// valid なマスから走査を始め、壁で止まる限りこれは成り立つ。
+#[inline]
+fn ray_cell(board: &Board, sq: Sq) -> BoardCell {
+    debug_assert!(sq.is_ok());
+
+    #[cfg(feature = "unsafe-fast")]
+    let cell = unsafe { *board.cell_unchecked(sq) };
+    #[cfg(not(feature = "unsafe-fast"))]
+    let cell = board[sq];
+
+    cell
+}
+
 /// board 上で sq から dir 方向への遠隔利きを列挙する。
 /// sq に駒があるかどうかは気にしない。
 fn iter_uni_ranged_effects_by(board: &Board, sq: Sq, dir: i32) -> impl Iterator<Item = Sq> + '_ {
@@ -112,10 +203,11 @@ fn iter_uni_ranged_effects_by(board: &Board, sq: Sq, dir: i32) -> impl Iterator<
         if !*ok {
             return None;
         }
-        if board[dst].is_wall() {
+        let cell = ray_cell(board, dst);
+        if cell.is_wall() {
             return None;
         }
-        if !board[dst].is_empty() {
+        if !cell.is_empty() {
             *ok = false;
         }
         Some(dst)
@@ -202,7 +294,7 @@ fn iter_uni_ranged_support_effects_by(
 
     std::iter::repeat_with(it).scan(State::Normal, move |state, dst| match state {
         State::Normal => {
-            let cell = board[dst];
+            let cell = ray_cell(board, dst);
             if cell.is_wall() {
                 return None;
             }
@@ -219,7 +311,7 @@ fn iter_uni_ranged_support_effects_by(
             Some((false, dst))
         }
         State::Support => {
-            if board[dst].is_wall() {
+            if ray_cell(board, dst).is_wall() {
                 return None;
             }
             *state = State::Break;
@@ -260,7 +352,7 @@ impl EffectInfo {
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct EffectBoardCell([EffectInfo; 2]);
+pub struct EffectBoardCell(SideArray<EffectInfo>);
 
 impl std::ops::Index<Side> for EffectBoardCell {
     type Output = EffectInfo;
@@ -315,6 +407,60 @@ impl EffectBoard {
 
         this
     }
+
+    /// pos に mv を適用した局面の利き計算を行う (from_board() と同じ方式)。
+    /// pos を一時的に変更して計算し、元に戻す。mv の適用に失敗した場合はエラーを返す。
+    ///
+    /// 駒打ちでは手番の情報が要るので、Board でなく Position をとる。
+    pub fn after_move(pos: &mut Position, my: Side, mv: &Move) -> Result<Self> {
+        position::with_move(pos, mv, |pos| Self::from_board(pos.board(), my))
+    }
+
+    /// 影の利きを含めずに board 上の利き計算を行う。
+    /// 通常の意味での利きの分布が欲しい場合に用いる。原作の思考ルーチンでは使われない。
+    ///
+    /// 利きは iter_effects() で列挙する。
+    /// 影の利きは attacker に影響しないので、from_board() との違いは本質的には利き数のみとなる。
+    /// ただし列挙順が異なるため、同じ価値の駒が複数利いているマスでは attacker の駒種が
+    /// from_board() と異なりうる (価値は等しい)。
+    /// my は from_board() とシグネチャを揃えるためのもので、結果には影響しない。
+    pub fn from_board_plain(board: &Board, _my: Side) -> Self {
+        let mut this = Self::empty();
+
+        for side in Side::iter() {
+            for (src, dst) in iter_effects(board, side) {
+                let info = &mut this[dst][side];
+
+                info.count += 1;
+
+                let pt = board[src].piece_of(side).unwrap();
+                util::opt_chmin_by_key(&mut info.attacker, pt, |&p| PRICES_0[p]);
+            }
+        }
+
+        this
+    }
+
+    /// sq で side 側が取り返す場合に使う駒 (attacker) の価値を返す。
+    /// side 側の利きがなければ None を返す。
+    ///
+    /// 価値は駒得マス判定で attacker の比較に使われる PRICES_1 による。
+    /// 影の利きしかない場合も attacker がないので None となる。
+    pub fn recapture_value(&self, sq: Sq, side: Side) -> Option<u8> {
+        self[sq][side].attacker().map(|pt| PRICES_1[pt])
+    }
+
+    /// sq において利き数が真に多い側を返す。
+    /// 利き数が等しい場合(互いに利きがない場合も含む)は None を返す。
+    pub fn dominant(&self, sq: Sq) -> Option<Side> {
+        let count_sente = self[sq][Side::Sente].count();
+        let count_gote = self[sq][Side::Gote].count();
+        match count_sente.cmp(&count_gote) {
+            std::cmp::Ordering::Greater => Some(Side::Sente),
+            std::cmp::Ordering::Less => Some(Side::Gote),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
 }
 
 impl std::ops::Index<Sq> for EffectBoard {
@@ -330,3 +476,51 @@ impl std::ops::IndexMut<Sq> for EffectBoard {
         &mut self.cells[sq.get() as usize]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_board_plain() {
+        // from_board() から影の利きを除いたものと、利き数および attacker の価値が一致する
+        fn from_board_without_support(board: &Board, my: Side) -> EffectBoard {
+            let mut this = EffectBoard::empty();
+            for side in Side::iter() {
+                for (_, src, dst) in
+                    iter_support_effects(board, side, my).filter(|(is_support, _, _)| !is_support)
+                {
+                    let info = &mut this[dst][side];
+                    info.count += 1;
+                    let pt = board[src].piece_of(side).unwrap();
+                    util::opt_chmin_by_key(&mut info.attacker, pt, |&p| PRICES_0[p]);
+                }
+            }
+            this
+        }
+
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..100 {
+            let pos = Position::random(&mut rng);
+            for my in Side::iter() {
+                let actual = EffectBoard::from_board_plain(pos.board(), my);
+                let expected = from_board_without_support(pos.board(), my);
+                for sq in Sq::iter_valid() {
+                    for side in Side::iter() {
+                        let price = |info: &EffectInfo| info.attacker().map(|pt| PRICES_0[pt]);
+                        assert_eq!(actual[sq][side].count(), expected[sq][side].count());
+                        assert_eq!(price(&actual[sq][side]), price(&expected[sq][side]));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_iter_effects_by_invalid_sq() {
+        let board = Board::empty();
+        let _ = iter_effects_by(&board, Side::Sente, Sq::new(0), Piece::Rook);
+    }
+}
@@ -12,6 +12,7 @@ use crate::ai::{BestEval, CandEval, PositionEval, RootEval};
 use crate::book::{BookState, Formation};
 use crate::effect::{EffectBoard, EffectInfo};
 use crate::prelude::*;
+use crate::record::RecordEntry;
 use crate::util;
 use crate::{Error, Result};
 
@@ -411,6 +412,45 @@ pub const ADDRS_TWEAK: &[u16] = &[
     0xF674,
 ];
 
+/// 対局の状態
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GamePhase {
+    Playing,
+    YourSuicide,
+    YourWin,
+    MyWin,
+}
+
+impl GamePhase {
+    /// フックされたアドレスから、対局状態の遷移先を返す。
+    /// 終局処理以外のアドレスなら None を返す。
+    ///
+    /// 対局状態そのものを保持する RAM 上のフラグは未特定なので、終局処理のアドレスで判定している。
+    pub fn from_hook_addr(addr: u16) -> Option<Self> {
+        match addr {
+            ADDR_YOUR_SUICIDE => Some(Self::YourSuicide),
+            ADDR_YOUR_WIN => Some(Self::YourWin),
+            ADDR_MY_WIN => Some(Self::MyWin),
+            _ => None,
+        }
+    }
+
+    pub fn is_over(self) -> bool {
+        !matches!(self, Self::Playing)
+    }
+
+    /// 終局状態に対応する棋譜要素を返す。MyWin の場合は思考ルーチンの指し手をエミュレータから読む。
+    /// Playing なら None を返す。
+    pub fn record_entry(self) -> Option<RecordEntry> {
+        match self {
+            Self::Playing => None,
+            Self::YourSuicide => Some(RecordEntry::YourSuicide),
+            Self::YourWin => Some(RecordEntry::YourWin),
+            Self::MyWin => Some(RecordEntry::MyWin(get_my_move())),
+        }
+    }
+}
+
 pub fn decode_sq(value: u8) -> Sq {
     if value == 99 {
         return SQ_INVALID;
@@ -852,9 +892,27 @@ pub fn start_game(handicap: Handicap, timelimit: bool) {
     run_frame(BTNS_T);
 }
 
+/// start_game() 直後のカーソル位置を返す。
+///
+/// 平手 (your 先手) では 5五 であることを traveller_test で確認している。
+/// 手合割ごとの違いは確認できていないので引数には取らず、全て 5五 とみなす。
+/// verify は初手でこの仮定をエミュレータのカーソル位置と照合する。
+pub fn initial_cursor() -> Cursor {
+    Cursor::board(Sq::from_xy(5, 5))
+}
+
 /// your 側の指し手を実行する。
 /// 着手から 20 フレームほど演出が入るので、この過程で思考ルーチンが実行されることはない。
+///
+/// カーソル位置はエミュレータから読み取る。
+/// 初手では代わりに move_your_from(&initial_cursor(), ...) を使えば読み取りが不要となる。
 pub fn move_your(mv: &Move, your: Side) {
+    move_your_from(&get_cursor(), mv, your);
+}
+
+/// カーソル位置 cursor から your 側の指し手を実行し、着手後のカーソル位置 (移動先) を返す。
+/// 初手では initial_cursor() を渡せばエミュレータからカーソル位置を読み取らずに済む。
+pub fn move_your_from(cursor: &Cursor, mv: &Move, your: Side) -> Cursor {
     fn move_cursor(src: &Cursor, dst: &Cursor, interval: i32) {
         let i = Traveller::vertex_cursor(src);
         let j = Traveller::vertex_cursor(dst);
@@ -881,7 +939,7 @@ pub fn move_your(mv: &Move, your: Side) {
 
     // あまり高速に入力すると認識されないことがあるので、若干余裕を持たせている
 
-    move_cursor(&get_cursor(), &src, 3);
+    move_cursor(cursor, &src, 3);
 
     run_frames(3, BTNS_A);
     run_frames(4, BTNS_NONE);
@@ -898,4 +956,6 @@ pub fn move_your(mv: &Move, your: Side) {
         }
         run_frames(3, BTNS_A);
     }
+
+    dst
 }
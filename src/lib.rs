@@ -14,12 +14,15 @@
 //! "my" は思考ルーチン側、"your" は対戦相手側の意。
 //!===================================================================
 
+use boolinator::Boolinator;
 use either::Either;
+use once_cell::sync::Lazy;
 
 #[macro_use]
 mod util;
 
 pub mod ai;
+pub mod analysis;
 pub mod book;
 pub mod effect;
 pub mod log;
@@ -39,6 +42,7 @@ pub mod your_player;
 pub mod emu;
 
 use position::Position;
+use pretty::Pretty;
 
 //--------------------------------------------------------------------
 // util
@@ -139,6 +143,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 //--------------------------------------------------------------------
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Side {
     Sente,
     Gote,
@@ -183,20 +188,78 @@ impl Side {
     }
 }
 
-/// 長さ 2 の配列の添字として使えると便利なので
-impl<T> std::ops::Index<Side> for [T] {
+impl std::fmt::Display for Side {
+    /// USI 形式 (b/w) で出力する。
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&sfen::side_to_sfen(*self))
+    }
+}
+
+impl std::str::FromStr for Side {
+    type Err = Error;
+
+    /// b/w (USI 形式), sente/gote, 先手/後手 を受け付ける。
+    ///
+    /// ```
+    /// # use naitou_clone::prelude::*;
+    /// assert_eq!("w".parse::<Side>().unwrap(), Side::Gote);
+    /// assert_eq!("sente".parse::<Side>().unwrap(), Side::Sente);
+    /// assert_eq!("後手".parse::<Side>().unwrap(), Side::Gote);
+    /// assert_eq!(Side::Sente.to_string(), "b");
+    /// assert!("x".parse::<Side>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sente" | "先手" => Ok(Self::Sente),
+            "gote" | "後手" => Ok(Self::Gote),
+            _ => sfen::sfen_to_side(s),
+        }
+    }
+}
+
+/// 先手と後手の値の組。arr[side] のようにアクセスする。
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SideArray<T>([T; 2]);
+
+impl<T> SideArray<T> {
+    pub const fn new(sente: T, gote: T) -> Self {
+        Self([sente, gote])
+    }
+
+    /// 先手, 後手の順に要素を列挙する。
+    pub fn iter(&self) -> std::slice::Iter<'_, T> {
+        self.0.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::slice::IterMut<'_, T> {
+        self.0.iter_mut()
+    }
+
+    /// 各要素に f を適用した SideArray を返す。
+    ///
+    /// ```
+    /// # use naitou_clone::prelude::*;
+    /// let arr = SideArray::new(1, 2).map(|x| 10 * x);
+    /// assert_eq!(arr[Side::Sente], 10);
+    /// assert_eq!(arr[Side::Gote], 20);
+    /// ```
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> SideArray<U> {
+        let [sente, gote] = self.0;
+        SideArray::new(f(sente), f(gote))
+    }
+}
+
+impl<T> std::ops::Index<Side> for SideArray<T> {
     type Output = T;
 
     fn index(&self, side: Side) -> &Self::Output {
-        debug_assert_eq!(self.len(), 2);
-        &self[side as usize]
+        &self.0[side as usize]
     }
 }
 
-impl<T> std::ops::IndexMut<Side> for [T] {
+impl<T> std::ops::IndexMut<Side> for SideArray<T> {
     fn index_mut(&mut self, side: Side) -> &mut Self::Output {
-        debug_assert_eq!(self.len(), 2);
-        &mut self[side as usize]
+        &mut self.0[side as usize]
     }
 }
 
@@ -255,6 +318,19 @@ impl SqX {
             Side::Gote => self.inv(),
         }
     }
+
+    /// USI の筋の文字 ('1'..='9') に対応する x 座標を返す。
+    pub fn from_usi_char(c: char) -> Option<Self> {
+        ('1'..='9')
+            .contains(&c)
+            .as_some_from(|| Self(10 - (c as i32 - '0' as i32)))
+    }
+
+    /// USI の筋の文字 ('1'..='9') を返す。self は valid でなければならない。
+    pub fn to_usi_char(&self) -> char {
+        assert!(self.is_valid());
+        char::from(b'0' + (10 - self.0) as u8)
+    }
 }
 
 impl_add_sub!(SqX);
@@ -315,6 +391,19 @@ impl SqY {
         }
     }
 
+    /// USI の段の文字 ('a'..='i') に対応する y 座標を返す。
+    pub fn from_usi_char(c: char) -> Option<Self> {
+        ('a'..='i')
+            .contains(&c)
+            .as_some_from(|| Self(c as i32 - 'a' as i32 + 1))
+    }
+
+    /// USI の段の文字 ('a'..='i') を返す。self は valid でなければならない。
+    pub fn to_usi_char(&self) -> char {
+        assert!(self.is_valid());
+        char::from(b'a' + (self.0 - 1) as u8)
+    }
+
     /// side から見て成れる段かどうかを返す。
     pub fn can_promote(&self, side: Side) -> bool {
         (1..=3).contains(&self.rel(side).0)
@@ -339,6 +428,7 @@ impl_add_sub!(SqY);
 //--------------------------------------------------------------------
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Sq(i32);
 
 impl Sq {
@@ -366,6 +456,33 @@ impl Sq {
         (self.x(), self.y())
     }
 
+    /// USI 形式のマス表記 (例: "7f") からマスを得る。valid なマスでなければエラーとなる。
+    ///
+    /// ```
+    /// # use naitou_clone::prelude::*;
+    /// let sq = Sq::from_usi("7f").unwrap();
+    /// assert_eq!(sq, Sq::from_xy(3, 6));
+    /// assert_eq!(sq.to_usi(), "7f");
+    /// assert_eq!(sq.x().to_usi_char(), '7');
+    /// assert_eq!(sq.y().to_usi_char(), 'f');
+    /// assert!(Sq::from_usi("0a").is_err());
+    /// assert!(Sq::from_usi("1j").is_err());
+    /// assert!(Sq::from_usi("7f+").is_err());
+    /// ```
+    pub fn from_usi(s: &str) -> Result<Self> {
+        let cs: Vec<_> = s.chars().collect();
+        chk!(
+            cs.len() == 2,
+            Error::invalid_sfen(format!("invalid square: {:?}", s))
+        );
+        sfen::chars_to_sq(cs[0], cs[1])
+    }
+
+    /// USI 形式のマス表記 (例: "7f") を返す。self は valid でなければならない。
+    pub fn to_usi(&self) -> String {
+        format!("{}{}", self.x().to_usi_char(), self.y().to_usi_char())
+    }
+
     pub fn is_ok(&self) -> bool {
         (0..11 * 11).contains(&self.0)
     }
@@ -404,13 +521,52 @@ impl Sq {
         }
     }
 
+    /// 周囲 8 マスのうち valid なものたちを昇順で返す。
+    pub fn neighbors(&self) -> impl Iterator<Item = Self> {
+        self.neighbors_within(1)
+    }
+
+    /// 自身とのチェス盤距離が 1 以上 radius 以下である valid なマスたちを昇順で返す。
+    pub fn neighbors_within(&self, radius: i32) -> impl Iterator<Item = Self> {
+        let (x, y) = (self.x().get(), self.y().get());
+        (y - radius..=y + radius)
+            .filter(|&yy| SqY(yy).is_valid())
+            .flat_map(move |yy| {
+                (x - radius..=x + radius)
+                    .filter(|&xx| SqX(xx).is_valid())
+                    .map(move |xx| Self::from_xy(xx, yy))
+            })
+            .filter(move |&sq| sq != Self::from_xy(x, y))
+    }
+
     /// いわゆるチェス盤距離を返す。
     /// sq1, sq2 のいずれかが ok でない場合、None を返す。
+    ///
+    /// 評価関数の内側のループで多用されるので、dist_table() を引く。
     pub fn dist(sq1: Self, sq2: Self) -> Option<i32> {
-        match (Sq::dist_x(sq1, sq2), Sq::dist_y(sq1, sq2)) {
-            (Some(d1), Some(d2)) => Some(std::cmp::max(d1, d2)),
-            _ => None,
+        if !sq1.is_ok() || !sq2.is_ok() {
+            return None;
         }
+        Some(i32::from(
+            Self::dist_table()[sq1.0 as usize][sq2.0 as usize],
+        ))
+    }
+
+    /// ok なマス同士のチェス盤距離のテーブルを返す。table[sq1][sq2] のようにアクセスする。
+    pub fn dist_table() -> &'static [[u8; 11 * 11]; 11 * 11] {
+        static TABLE: Lazy<[[u8; 11 * 11]; 11 * 11]> = Lazy::new(|| {
+            let mut table = [[0; 11 * 11]; 11 * 11];
+            for sq1 in Sq::iter_ok() {
+                for sq2 in Sq::iter_ok() {
+                    let dx = (sq1.x().get() - sq2.x().get()).abs();
+                    let dy = (sq1.y().get() - sq2.y().get()).abs();
+                    table[sq1.0 as usize][sq2.0 as usize] = std::cmp::max(dx, dy) as u8;
+                }
+            }
+            table
+        });
+
+        &TABLE
     }
 
     /// x 方向の距離を返す。
@@ -546,6 +702,42 @@ impl Piece {
         }
     }
 
+    /// SFEN の駒文字 (大文字小文字は問わない) に対応するナマ駒を返す。
+    /// 成駒は '+' 前置で表されるので、この関数では扱わない。
+    pub fn from_sfen_char(c: char) -> Option<Self> {
+        match c.to_ascii_uppercase() {
+            'P' => Some(Self::Pawn),
+            'L' => Some(Self::Lance),
+            'N' => Some(Self::Knight),
+            'S' => Some(Self::Silver),
+            'B' => Some(Self::Bishop),
+            'R' => Some(Self::Rook),
+            'G' => Some(Self::Gold),
+            'K' => Some(Self::King),
+            _ => None,
+        }
+    }
+
+    /// SFEN における駒の表記 (先手の大文字。成駒は "+P" など) を返す。
+    pub fn to_sfen_char(&self) -> &'static str {
+        match self {
+            Self::Pawn => "P",
+            Self::Lance => "L",
+            Self::Knight => "N",
+            Self::Silver => "S",
+            Self::Bishop => "B",
+            Self::Rook => "R",
+            Self::Gold => "G",
+            Self::King => "K",
+            Self::ProPawn => "+P",
+            Self::ProLance => "+L",
+            Self::ProKnight => "+N",
+            Self::ProSilver => "+S",
+            Self::Horse => "+B",
+            Self::Dragon => "+R",
+        }
+    }
+
     /// 持駒となりうる駒たちを昇順で返す。
     pub fn iter_hand() -> impl Iterator<Item = Self> {
         [
@@ -673,6 +865,13 @@ impl MoveNondrop {
     }
 }
 
+impl std::fmt::Display for MoveNondrop {
+    /// USI 形式 (例: 7g7f, 8h2b+) で出力する。
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Move::Nondrop(self.clone()).fmt(f)
+    }
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct MoveDrop {
     pt: Piece,
@@ -696,6 +895,23 @@ impl MoveDrop {
     }
 }
 
+impl std::fmt::Display for MoveDrop {
+    /// USI 形式 (例: P*5e) で出力する。
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        Move::Drop(self.clone()).fmt(f)
+    }
+}
+
+/// 指し手の種類。王手かどうかは含まない (Move::gives_check() を参照)。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MoveKind {
+    QuietMove,
+    Capture(Piece),          // 取った駒 (盤上での駒種)
+    Promotion,               // 駒を取らない成り
+    PromotionCapture(Piece), // 駒を取る成り
+    Drop(Piece),
+}
+
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Move {
     Nondrop(MoveNondrop),
@@ -715,6 +931,11 @@ impl Move {
         sfen::sfen_to_move(sfen)
     }
 
+    /// USI 形式の文字列を返す。sfen::move_to_sfen を参照。
+    pub fn to_sfen(&self) -> String {
+        sfen::move_to_sfen(self).into_owned()
+    }
+
     pub fn is_nondrop(&self) -> bool {
         matches!(self, Self::Nondrop(_))
     }
@@ -743,6 +964,197 @@ impl Move {
             Self::Drop(_) => false,
         }
     }
+
+    /// 局面 pos における指し手として正規化する。
+    /// 不成だと行きどころのない駒になる場合、成りに直す。
+    /// 成れない駒の成り、および行きどころのない駒が生じる手はエラーとなる。
+    ///
+    /// 強制成りの "+" を省略した外部の指し手を取り込む際に用いる。
+    pub fn normalize(self, pos: &Position) -> Result<Self> {
+        let side = pos.side();
+
+        match &self {
+            Self::Nondrop(nondrop) => {
+                let src = nondrop.src;
+                let dst = nondrop.dst;
+
+                let pt = pos.board()[src]
+                    .piece_of(side)
+                    .ok_or_else(|| Error::illegal_move(&self, "src is not my piece"))?;
+
+                let is_promotion = if nondrop.is_promotion {
+                    chk!(
+                        can_promote(side, pt, src, dst),
+                        Error::illegal_move(&self, "cannot promote")
+                    );
+                    true
+                } else if dst.can_put(side, pt) {
+                    false
+                } else {
+                    chk!(
+                        can_promote(side, pt, src, dst),
+                        Error::illegal_move(&self, "dead piece")
+                    );
+                    true
+                };
+
+                Ok(Self::nondrop(src, dst, is_promotion))
+            }
+            Self::Drop(drop) => {
+                chk!(
+                    drop.dst.can_put(side, drop.pt),
+                    Error::illegal_move(&self, "dead piece")
+                );
+                Ok(self)
+            }
+        }
+    }
+
+    /// 手番側の指し手として種類を判定する。合法性は調べない。
+    pub fn kind(&self, pos: &Position) -> MoveKind {
+        match self {
+            Self::Nondrop(nondrop) => {
+                let pt_capture = pos.board()[nondrop.dst].piece_of(pos.side().inv());
+                match (nondrop.is_promotion, pt_capture) {
+                    (false, None) => MoveKind::QuietMove,
+                    (false, Some(pt)) => MoveKind::Capture(pt),
+                    (true, None) => MoveKind::Promotion,
+                    (true, Some(pt)) => MoveKind::PromotionCapture(pt),
+                }
+            }
+            Self::Drop(drop) => MoveKind::Drop(drop.pt),
+        }
+    }
+
+    /// 手番側がこの手を指すと相手玉に王手が掛かるかどうかを返す(開き王手を含む)。
+    /// 適用できない手なら false を返す。
+    pub fn gives_check(&self, pos: &Position) -> bool {
+        match self {
+            Self::Nondrop(_) => {
+                let side = pos.side();
+                let mut pos = pos.clone();
+                if pos.do_move(self).is_err() {
+                    return false;
+                }
+                pos.in_check(side.inv())
+            }
+            Self::Drop(drop) => your_move::drop_gives_check(pos, drop.pt, drop.dst),
+        }
+    }
+
+    /// 棋譜の表記に近い形式 ("７六歩", "同　金", "５八金右" など) で表した文字列を返す。
+    /// 同じ駒種の駒が複数 dst に利いている場合、上/引/寄, 右/左/直 を付加して区別する。
+    ///
+    /// dst_prev は直前の指し手の移動先で、dst と一致すれば "同　" を用いる。
+    ///
+    /// ```
+    /// # use naitou_clone::prelude::*;
+    /// let pos = Position::from_sfen("sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();
+    /// let mv = Move::nondrop(Sq::from_xy(3, 7), Sq::from_xy(3, 6), false);
+    /// assert_eq!(mv.to_display_with_context(&pos, None), "７六歩");
+    /// let mv = Move::nondrop(Sq::from_xy(6, 9), Sq::from_xy(5, 8), false);
+    /// assert_eq!(mv.to_display_with_context(&pos, None), "５八金右");
+    /// ```
+    pub fn to_display_with_context(&self, pos: &Position, dst_prev: Option<Sq>) -> String {
+        let side = pos.side();
+        let board = pos.board();
+        let dst = self.dst();
+
+        let mut res = if dst_prev == Some(dst) {
+            "同　".to_owned()
+        } else {
+            dst.pretty().into_owned()
+        };
+
+        match self {
+            Self::Nondrop(nondrop) => {
+                let src = nondrop.src;
+                let pt = match board[src].piece_of(side) {
+                    Some(pt) => pt,
+                    None => return self.pretty().into_owned(),
+                };
+                res.push_str(&pt.pretty());
+
+                let srcs: Vec<_> = effect::reachable_from(board, side, dst, pt).collect();
+                if srcs.len() >= 2 {
+                    res.push_str(&disambiguate(side, pt, src, dst, &srcs));
+                }
+
+                if nondrop.is_promotion {
+                    res.push('成');
+                } else if can_promote(side, pt, src, dst) {
+                    res.push_str("不成");
+                }
+            }
+            Self::Drop(drop) => {
+                res.push_str(&drop.pt.pretty());
+                // 盤上の同種の駒が dst に利いている場合のみ "打" を付ける
+                if effect::reachable_from(board, side, dst, drop.pt)
+                    .next()
+                    .is_some()
+                {
+                    res.push('打');
+                }
+            }
+        }
+
+        res
+    }
+}
+
+impl std::fmt::Display for Move {
+    /// USI 形式で出力する。Move::to_sfen() と同じ。
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&sfen::move_to_sfen(self))
+    }
+}
+
+/// 同じ駒種の駒が複数 dst に利いているとき、src の駒を区別する文字列を返す。
+fn disambiguate(side: Side, pt: Piece, src: Sq, dst: Sq, srcs: &[Sq]) -> String {
+    // 手番側から見た前進量と右方向の座標
+    let forward = |sq: Sq| side.sgn() * (sq.y().get() - dst.y().get());
+    let right = |sq: Sq| side.sgn() * sq.x().get();
+    let motion = |sq: Sq| forward(sq).signum();
+
+    let motion_str = match motion(src) {
+        1 => "上",
+        -1 => "引",
+        _ => "寄",
+    };
+
+    // 動作だけで区別できる場合
+    if srcs.iter().filter(|&&sq| motion(sq) == motion(src)).count() == 1 {
+        return motion_str.to_owned();
+    }
+
+    let is_straight = motion(src) == 1 && src.x() == dst.x();
+    let lateral = |group: &[Sq]| {
+        if is_straight && !matches!(pt, Piece::Horse | Piece::Dragon) {
+            Some("直")
+        } else if group.iter().all(|&sq| sq == src || right(sq) < right(src)) {
+            Some("右")
+        } else if group.iter().all(|&sq| sq == src || right(sq) > right(src)) {
+            Some("左")
+        } else {
+            None
+        }
+    };
+
+    // 位置だけで区別できる場合
+    if let Some(s) = lateral(srcs) {
+        return s.to_owned();
+    }
+
+    // 同じ動作をする駒の中での位置と動作を併用する
+    let group: Vec<_> = srcs
+        .iter()
+        .copied()
+        .filter(|&sq| motion(sq) == motion(src))
+        .collect();
+    match lateral(&group) {
+        Some(s) => format!("{}{}", s, motion_str),
+        None => motion_str.to_owned(),
+    }
 }
 
 //--------------------------------------------------------------------
@@ -815,6 +1227,15 @@ impl BoardCell {
             _ => false,
         }
     }
+
+    /// 駒の陣営を入れ替えたマスを返す。空白と壁はそのまま。
+    pub fn inv(&self) -> Self {
+        match self {
+            Self::Sente(pt) => Self::Gote(*pt),
+            Self::Gote(pt) => Self::Sente(*pt),
+            cell => *cell,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -836,6 +1257,95 @@ impl Board {
         Self { cells }
     }
 
+    /// 盤面の SFEN 表記を buf の末尾に追記する。sfen::board_to_sfen_into を参照。
+    pub fn to_sfen_into(&self, buf: &mut String) {
+        sfen::board_to_sfen_into(self, buf);
+    }
+
+    /// 9x9 の文字配列から盤面を得る。文字の割り当ては sfen::grid_to_board を参照。
+    pub fn from_grid(rows: &[[char; 9]; 9]) -> Result<Self> {
+        sfen::grid_to_board(rows)
+    }
+
+    /// 盤面を 180 度回し、駒の陣営を入れ替えた盤面を返す。
+    pub fn inv(&self) -> Self {
+        let mut res = Self::empty();
+        for sq in Sq::iter_ok() {
+            res[sq.inv()] = self[sq].inv();
+        }
+        res
+    }
+
+    /// 盤面を左右反転した盤面を返す。
+    pub fn mirror(&self) -> Self {
+        let mut res = Self::empty();
+        for sq in Sq::iter_valid() {
+            res[Sq::from_xy(10 - sq.x().get(), sq.y().get())] = self[sq];
+        }
+        res
+    }
+
+    /// 盤上の駒を陣営別・駒種別に数える。census[side][pt as usize] のようにアクセスする。
+    pub fn census(&self) -> SideArray<[u8; 14]> {
+        let mut res = SideArray::new([0; 14], [0; 14]);
+
+        for (_, side, pt) in self.iter_pieces() {
+            res[side][pt as usize] += 1;
+        }
+
+        res
+    }
+
+    /// 盤上の駒を (マス, 陣営, 駒種) としてマスの昇順に列挙する。空白マスと壁は含まない。
+    ///
+    /// ```
+    /// # use naitou_clone::prelude::*;
+    /// let pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/4+P4/4K4 b - 1").unwrap();
+    /// let pieces: Vec<_> = pos.board().iter_pieces().collect();
+    /// assert_eq!(
+    ///     pieces,
+    ///     [
+    ///         (Sq::from_xy(5, 1), Side::Gote, Piece::King),
+    ///         (Sq::from_xy(5, 8), Side::Sente, Piece::ProPawn),
+    ///         (Sq::from_xy(5, 9), Side::Sente, Piece::King),
+    ///     ]
+    /// );
+    /// ```
+    pub fn iter_pieces(&self) -> impl Iterator<Item = (Sq, Side, Piece)> + '_ {
+        Sq::iter_valid().filter_map(move |sq| match self[sq] {
+            BoardCell::Sente(pt) => Some((sq, Side::Sente, pt)),
+            BoardCell::Gote(pt) => Some((sq, Side::Gote, pt)),
+            _ => None,
+        })
+    }
+
+    /// 盤上にある side 側の駒 pt の個数を返す。成駒とナマ駒は区別する。
+    ///
+    /// ```
+    /// # use naitou_clone::prelude::*;
+    /// let pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/P8/4+P4/4K4 b - 1").unwrap();
+    /// assert_eq!(pos.board().count(Side::Sente, Piece::Pawn), 1);
+    /// assert_eq!(pos.board().count(Side::Sente, Piece::ProPawn), 1);
+    /// assert_eq!(pos.board().count(Side::Gote, Piece::Pawn), 0);
+    /// ```
+    pub fn count(&self, side: Side, pt: Piece) -> u8 {
+        self.iter_pieces()
+            .filter(|&(_, s, p)| s == side && p == pt)
+            .count() as u8
+    }
+
+    /// 各マスの Pretty 文字列 (" 歩", "v金", " 口" など) を [y][x] の順の行列として返す。
+    /// 添字は 0 始まりで、[0][0] が 9一 に対応する。
+    pub fn to_kanji_rows(&self) -> [[String; 9]; 9] {
+        array_init::array_init(|y| {
+            array_init::array_init(|x| {
+                self[Sq::from_xy(x as i32 + 1, y as i32 + 1)]
+                    .pretty()
+                    .into_owned()
+            })
+        })
+    }
+
     pub fn row(&self, y: i32) -> &[BoardCell] {
         let y = y as usize;
         &self.cells[11 * y..11 * (y + 1)]
@@ -857,6 +1367,19 @@ impl Board {
     }
 }
 
+#[cfg(feature = "unsafe-fast")]
+impl Board {
+    /// sq のマスを境界チェックなしで返す。利き計算などの内側のループ用。
+    ///
+    /// # Safety
+    ///
+    /// sq は ok (sq.is_ok()) でなければならない。
+    pub unsafe fn cell_unchecked(&self, sq: Sq) -> &BoardCell {
+        debug_assert!(sq.is_ok());
+        self.cells.get_unchecked(sq.get() as usize)
+    }
+}
+
 impl std::ops::Index<Sq> for Board {
     type Output = BoardCell;
 
@@ -886,6 +1409,23 @@ impl Hand {
     pub fn is_empty(&self) -> bool {
         self.0.iter().all(|&count| count == 0)
     }
+
+    /// 持駒 pt の枚数を返す。pt が持駒となりえない駒なら None を返す。
+    pub fn get(&self, pt: Piece) -> Option<&u8> {
+        if pt.is_hand() {
+            self.0.get(pt as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, pt: Piece) -> Option<&mut u8> {
+        if pt.is_hand() {
+            self.0.get_mut(pt as usize)
+        } else {
+            None
+        }
+    }
 }
 
 impl std::ops::Index<Piece> for Hand {
@@ -907,20 +1447,39 @@ impl std::ops::IndexMut<Piece> for Hand {
 /// 先手と後手の持駒を束ねたもの。
 /// hands[side][pt] のようにアクセスする。
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
-pub struct Hands([Hand; 2]);
+pub struct Hands(SideArray<Hand>);
 
 impl Hands {
     pub fn empty() -> Self {
-        Self([Hand::empty(), Hand::empty()])
+        Self(SideArray::new(Hand::empty(), Hand::empty()))
     }
 
     pub fn new(hand_sente: Hand, hand_gote: Hand) -> Self {
-        Self([hand_sente, hand_gote])
+        Self(SideArray::new(hand_sente, hand_gote))
     }
 
     pub fn is_empty(&self) -> bool {
         self.0.iter().all(|hand| hand.is_empty())
     }
+
+    /// 先手と後手の持駒を入れ替えたものを返す。
+    pub fn inv(&self) -> Self {
+        Self::new(self[Side::Gote].clone(), self[Side::Sente].clone())
+    }
+
+    /// 持駒の SFEN 表記を buf の末尾に追記する。sfen::hands_to_sfen_into を参照。
+    pub fn to_sfen_into(&self, buf: &mut String) {
+        sfen::hands_to_sfen_into(self, buf);
+    }
+
+    /// side 側の持駒を (駒名, 枚数) のリストとして返す。枚数 0 の駒は含まない。
+    pub fn to_kanji(&self, side: Side) -> Vec<(String, u8)> {
+        let hand = &self[side];
+        Piece::iter_hand()
+            .filter(|&pt| hand[pt] > 0)
+            .map(|pt| (pt.pretty().into_owned(), hand[pt]))
+            .collect()
+    }
 }
 
 impl std::ops::Index<Side> for Hands {
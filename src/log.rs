@@ -82,6 +82,42 @@ impl Pretty for Log {
     }
 }
 
+/// 候補手ごとに最終的な評価値を 1 行にまとめた表を返す。
+/// 評価値の修正履歴を全て出力する Pretty for Log より見比べやすい。
+pub fn candidate_table(log: &Log) -> String {
+    use std::fmt::Write;
+
+    let mut res = String::new();
+
+    writeln!(res, "候補手       nega posi  adv disadv capture  更新").unwrap();
+
+    for cand_log in log.cand_logs.iter() {
+        // 全角文字を含むので、幅は文字数で揃える
+        let mv = cand_log.mv.pretty();
+        let pad = " ".repeat(12_usize.saturating_sub(2 * mv.chars().count()));
+        let improved = if cand_log.improved { "✓" } else { "" };
+
+        match cand_log.evals.last() {
+            Some(eval) => writeln!(
+                res,
+                "{}{} {:>4} {:>4} {:>4} {:>6} {:>7} {:>3}",
+                mv,
+                pad,
+                eval.nega,
+                eval.posi,
+                eval.adv_price,
+                eval.disadv_price,
+                eval.capture_price,
+                improved
+            ),
+            None => writeln!(res, "{}{} {:>37}", mv, pad, "-"),
+        }
+        .unwrap();
+    }
+
+    res
+}
+
 pub trait LoggerTrait {
     fn log_progress(&mut self, _ply: u8, _level: u8, _level_sub: u8);
     fn log_book_state(&mut self, _book_state: BookState);
@@ -98,6 +134,9 @@ pub trait LoggerTrait {
 
     fn log_best_eval(&mut self, _best_eval: BestEval);
     fn log_record_entry(&mut self, _record_entry: RecordEntry);
+
+    /// 定跡処理などに関する人間向けの注釈。
+    fn log_comment(&mut self, _comment: String);
 }
 
 #[derive(Debug, Default)]
@@ -211,6 +250,9 @@ impl LoggerTrait for Logger {
     fn log_record_entry(&mut self, record_entry: RecordEntry) {
         self.record_entry = Some(record_entry);
     }
+
+    // 注釈はエミュレータ側から得られないので、ログには含めない
+    fn log_comment(&mut self, _comment: String) {}
 }
 
 #[derive(Debug)]
@@ -238,4 +280,46 @@ impl LoggerTrait for NullLogger {
 
     fn log_best_eval(&mut self, _best_eval: BestEval) {}
     fn log_record_entry(&mut self, _record_entry: RecordEntry) {}
+
+    fn log_comment(&mut self, _comment: String) {}
+}
+
+/// 思考の統計を取るロガー。ベンチマーク用。
+/// ログ内容は保持せず、候補手数と利き計算の回数のみ数える。
+#[derive(Debug, Default)]
+pub struct ThinkStats {
+    pub n_cand: u64,      // 評価した候補手の数
+    pub n_eff_board: u64, // 利き計算 (EffectBoard 構築) の回数
+}
+
+impl ThinkStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl LoggerTrait for ThinkStats {
+    fn log_progress(&mut self, _ply: u8, _level: u8, _level_sub: u8) {}
+    fn log_book_state(&mut self, _book_state: BookState) {}
+
+    fn log_root_eval(&mut self, _root_eval: RootEval) {}
+    fn log_root_eff_board(&mut self, _eff_board: EffectBoard) {
+        self.n_eff_board += 1;
+    }
+
+    fn start_cand(&mut self, _mv: Move) {
+        self.n_cand += 1;
+    }
+    fn log_cand_eff_board(&mut self, _eff_board: EffectBoard) {
+        self.n_eff_board += 1;
+    }
+    fn log_cand_pos_eval(&mut self, _pos_eval: PositionEval) {}
+    fn log_cand_eval(&mut self, _cand_eval: CandEval) {}
+    fn log_cand_improve(&mut self) {}
+    fn end_cand(&mut self) {}
+
+    fn log_best_eval(&mut self, _best_eval: BestEval) {}
+    fn log_record_entry(&mut self, _record_entry: RecordEntry) {}
+
+    fn log_comment(&mut self, _comment: String) {}
 }
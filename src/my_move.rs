@@ -19,7 +19,7 @@ use boolinator::Boolinator;
 use either::Either;
 
 use crate::effect::{self, EffectBoard};
-use crate::position::PawnMask;
+use crate::position;
 use crate::prelude::*;
 
 /// book-legal 判定。
@@ -223,6 +223,84 @@ pub fn moves_pseudo_legal(pos: &Position) -> impl Iterator<Item = Move> + '_ {
     })
 }
 
+/// フラグ付きの生成手
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct GeneratedMove {
+    pub mv: Move,
+    pub forced_promotion: bool, // 成らないと行きどころのない駒になるため成りが強制されたか
+}
+
+/// moves_pseudo_legal() と同じ手を同じ順序で列挙し、各手に成りが強制されたかどうかのフラグを付ける。
+/// 思考ルーチンは成れる場合は常に成るので、成りが意味を持つ手の区別に使う。
+pub fn moves_with_flags(pos: &Position) -> impl Iterator<Item = GeneratedMove> + '_ {
+    let my = pos.side();
+
+    moves_pseudo_legal(pos).map(move |mv| {
+        let forced_promotion = match &mv {
+            Move::Nondrop(nondrop) if nondrop.is_promotion => {
+                let pt = pos.board()[nondrop.src].piece_of(my).unwrap();
+                !nondrop.dst.can_put(my, pt)
+            }
+            _ => false,
+        };
+        GeneratedMove {
+            mv,
+            forced_promotion,
+        }
+    })
+}
+
+/// my 側の src にある駒を動かす pseudo-legal を列挙する。
+/// src に my 駒がなければ空となる。
+pub fn moves_from(pos: &Position, src: Sq) -> Vec<Move> {
+    let my = pos.side();
+
+    match pos.board()[src].piece_of(my) {
+        Some(pt) => moves_pseudo_legal_nondrop(pos, src, pt).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// my 側の駒種 pt の駒による pseudo-legal を列挙する。盤上の pt の駒を動かす手、および pt を打つ手を含む。
+/// pt 以外の駒があるマスは指し手を生成せずに飛ばす。
+///
+/// 盤上の駒を動かす手、駒打ちの順に並ぶ(moves_pseudo_legal() とは順序が異なる)。
+pub fn moves_of_type(pos: &Position, pt: Piece) -> Vec<Move> {
+    let my = pos.side();
+
+    let mut mvs: Vec<_> = Sq::iter_valid_sim(my)
+        .filter(|&src| pos.board()[src].is_side_pt(my, pt))
+        .flat_map(|src| moves_pseudo_legal_nondrop(pos, src, pt))
+        .collect();
+
+    if pt.is_hand() {
+        mvs.extend(moves_drop(pos, pt));
+    }
+
+    mvs
+}
+
+/// my 側が持駒 pt を dst に打てるかどうかを返す。
+/// 思考ルーチンは打ち歩詰めを指さないので、打ち歩詰めは不可とする。
+pub fn can_drop(pos: &Position, pt: Piece, dst: Sq) -> bool {
+    position::can_drop(pos, pt, dst, false)
+}
+
+/// my 側の持駒 pt を打つ pseudo-legal を列挙する。
+/// 打ち歩詰めが含まれる。
+pub fn moves_drop(pos: &Position, pt: Piece) -> Vec<Move> {
+    let my = pos.side();
+
+    if pos.hand(my).get(pt).copied().unwrap_or(0) == 0 {
+        return Vec::new();
+    }
+
+    Sq::iter_valid_sim(my)
+        .flat_map(|dst| moves_pseudo_legal_drop(pos, dst))
+        .filter(|mv| mv.is_drop_pt(pt))
+        .collect()
+}
+
 fn moves_pseudo_legal_nondrop(
     pos: &Position,
     src: Sq,
@@ -355,28 +433,10 @@ fn moves_pseudo_legal_drop(pos: &Position, dst: Sq) -> impl Iterator<Item = Move
     ];
 
     let my = pos.side();
-    let pawn_mask = PawnMask::from_board_side(pos.board(), my);
+    let pawn_mask = pos.pawn_mask(my);
 
     let is_ok = move |drop: &MoveDrop| -> bool {
-        let pt = drop.pt;
-        let dst = drop.dst;
-
-        // 移動先が空白でないならNG
-        if !pos.board()[dst].is_empty() {
-            return false;
-        }
-
-        // 行きどころのない駒はNG
-        if !dst.can_put(my, pt) {
-            return false;
-        }
-
-        // 二歩はNG
-        if matches!(pt, Piece::Pawn) && pawn_mask.test(dst.x().get()) {
-            return false;
-        }
-
-        true
+        position::can_drop_on_board(pos.board(), my, pawn_mask, drop.pt, drop.dst)
     };
 
     let pts = PTS.iter().filter(move |&&pt| pos.hand(my)[pt] > 0);
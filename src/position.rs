@@ -1,9 +1,12 @@
+use once_cell::sync::Lazy;
 use rand::Rng;
 
 use crate::ai;
-use crate::effect;
+use crate::effect::{self, EffectBoard};
 use crate::prelude::*;
+use crate::price::PRICES_0;
 use crate::sfen;
+use crate::your_move;
 use crate::{Error, Result};
 
 //--------------------------------------------------------------------
@@ -19,9 +22,10 @@ impl PawnMask {
     }
 
     pub fn from_board_side(board: &Board, side: Side) -> Self {
-        let mask = (1..=9)
-            .filter(|&x| (1..=9).any(|y| board[Sq::from_xy(x, y)].is_side_pt(side, Piece::Pawn)))
-            .fold(0, |mask, x| mask | (1 << x));
+        let mask = board
+            .iter_pieces()
+            .filter(|&(_, s, pt)| s == side && pt == Piece::Pawn)
+            .fold(0, |mask, (sq, _, _)| mask | (1 << sq.x().get()));
 
         Self(mask)
     }
@@ -33,6 +37,10 @@ impl PawnMask {
     pub fn set(&mut self, x: i32) {
         self.0 |= 1 << x;
     }
+
+    pub fn clear(&mut self, x: i32) {
+        self.0 &= !(1 << x);
+    }
 }
 
 //--------------------------------------------------------------------
@@ -100,35 +108,57 @@ impl MoveCmd {
     }
 }
 
+/// do_move_annotated() が返す、指し手適用結果の注釈。
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MoveAnnotation {
+    pub pt_capture: Option<Piece>, // 取った駒 (成っていれば成駒のまま)
+    pub is_promotion: bool,
+    pub is_check: bool, // 指した後、相手玉に王手がかかっているか
+    pub is_mate: bool,  // 王手で、かつ相手に回避手がないか (evasion_count() による)
+}
+
 //--------------------------------------------------------------------
 // 局面
 //--------------------------------------------------------------------
 
-#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Position {
     side: Side,
     board: Board,
     hands: Hands,
     ply: i32,
+    pawn_masks: SideArray<PawnMask>, // board から求まる二歩チェック用キャッシュ
+    zobrist: u64,                    // side, board, hands から求まる Zobrist ハッシュのキャッシュ
+}
+
+/// 盤面全体を走査せず、Zobrist ハッシュと手数のみを用いる。
+impl std::hash::Hash for Position {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.zobrist.hash(state);
+        self.ply.hash(state);
+    }
 }
 
 impl Position {
     pub fn empty() -> Self {
-        Self {
-            side: Side::Sente,
-            board: Board::empty(),
-            hands: Hands::empty(),
-            ply: 1,
-        }
+        Self::new(Side::Sente, Board::empty(), Hands::empty(), 1)
     }
 
     pub fn new(side: Side, board: Board, hands: Hands, ply: i32) -> Self {
-        Self {
+        let pawn_masks = SideArray::new(
+            PawnMask::from_board_side(&board, Side::Sente),
+            PawnMask::from_board_side(&board, Side::Gote),
+        );
+        let mut pos = Self {
             side,
             board,
             hands,
             ply,
-        }
+            pawn_masks,
+            zobrist: 0,
+        };
+        pos.refresh_zobrist();
+        pos
     }
 
     pub fn from_sfen(sfen: impl AsRef<str>) -> crate::Result<Self> {
@@ -210,18 +240,14 @@ impl Position {
         let side = Side::random(rng);
         let ply = rng.gen_range(1, 256);
 
-        Self {
-            side,
-            board,
-            hands,
-            ply,
-        }
+        Self::new(side, board, hands, ply)
     }
 
     pub fn side(&self) -> Side {
         self.side
     }
 
+    /// 手番を直接変更した場合、refresh_zobrist() を呼ぶこと。
     pub fn side_mut(&mut self) -> &mut Side {
         &mut self.side
     }
@@ -230,6 +256,7 @@ impl Position {
         &self.board
     }
 
+    /// 盤面を直接変更した場合、refresh_pawn_masks() および refresh_zobrist() を呼ぶこと。
     pub fn board_mut(&mut self) -> &mut Board {
         &mut self.board
     }
@@ -238,6 +265,7 @@ impl Position {
         &self.hands
     }
 
+    /// 持駒を直接変更した場合、refresh_zobrist() を呼ぶこと。
     pub fn hands_mut(&mut self) -> &mut Hands {
         &mut self.hands
     }
@@ -246,6 +274,7 @@ impl Position {
         &self.hands[side]
     }
 
+    /// 持駒を直接変更した場合、refresh_zobrist() を呼ぶこと。
     pub fn hand_mut(&mut self, side: Side) -> &mut Hand {
         &mut self.hands[side]
     }
@@ -258,6 +287,87 @@ impl Position {
         &mut self.ply
     }
 
+    /// sq にある駒を (陣営, 駒種) として返す。駒がなければ None。
+    pub fn piece_at(&self, sq: Sq) -> Option<(Side, Piece)> {
+        match self.board[sq] {
+            BoardCell::Sente(pt) => Some((Side::Sente, pt)),
+            BoardCell::Gote(pt) => Some((Side::Gote, pt)),
+            _ => None,
+        }
+    }
+
+    /// sq にある駒の陣営を返す。駒がなければ None。
+    pub fn side_at(&self, sq: Sq) -> Option<Side> {
+        self.piece_at(sq).map(|(side, _)| side)
+    }
+
+    /// sq が空白マスかどうかを返す。壁は空白ではない。
+    pub fn is_empty(&self, sq: Sq) -> bool {
+        self.board[sq].is_empty()
+    }
+
+    /// side 側の玉のマスを返す。玉がなければ None。
+    pub fn king(&self, side: Side) -> Option<Sq> {
+        ai::find_king_sq(&self.board, side)
+    }
+
+    /// side 側の歩がある筋のマスクを返す。
+    /// do_move(), undo_move() で差分更新されるので盤面を走査しない。
+    pub fn pawn_mask(&self, side: Side) -> &PawnMask {
+        &self.pawn_masks[side]
+    }
+
+    /// board_mut() などで盤面を直接変更した後、二歩チェック用キャッシュを作り直す。
+    pub fn refresh_pawn_masks(&mut self) {
+        for side in Side::iter() {
+            self.pawn_masks[side] = PawnMask::from_board_side(&self.board, side);
+        }
+    }
+
+    /// side 側の x 筋の歩の有無を盤面から調べ直し、キャッシュに反映する。
+    fn update_pawn_mask(&mut self, side: Side, x: i32) {
+        if (1..=9).any(|y| self.board[Sq::from_xy(x, y)].is_side_pt(side, Piece::Pawn)) {
+            self.pawn_masks[side].set(x);
+        } else {
+            self.pawn_masks[side].clear(x);
+        }
+    }
+
+    /// side_mut() などで局面を直接変更した後、Zobrist ハッシュを計算し直す。
+    pub fn refresh_zobrist(&mut self) {
+        self.zobrist = self.compute_zobrist();
+    }
+
+    /// 盤上の sq に (side, pt) の駒を置く (または取り除く) 分だけ Zobrist ハッシュを更新する。
+    fn xor_zobrist_board(&mut self, side: Side, pt: Piece, sq: Sq) {
+        self.zobrist ^= ZOBRIST.board(side, pt, sq);
+    }
+
+    /// side の持駒 pt の枚数を delta だけ増減し、Zobrist ハッシュも更新する。
+    fn add_hand(&mut self, side: Side, pt: Piece, delta: i8) {
+        let before = self.hands[side][pt];
+        let after = (before as i8 + delta) as u8;
+        self.hands[side][pt] = after;
+        self.zobrist ^= ZOBRIST.hand(side, pt, before) ^ ZOBRIST.hand(side, pt, after);
+    }
+
+    /// 手数を除いて局面が等しいかどうかを返す。
+    pub fn eq_ignoring_ply(&self, other: &Self) -> bool {
+        self.side == other.side && self.board == other.board && self.hands == other.hands
+    }
+
+    /// history 内に現局面 (手数は無視) が現れる回数を返す。
+    /// 千日手判定用。
+    ///
+    /// 単純な線形探索なので O(history.len()) かかる。
+    /// 長い探索などで頻繁に呼ぶ場合は PositionHistory を使った方がよい。
+    pub fn repetition_count(&self, history: &[Position]) -> usize {
+        history
+            .iter()
+            .filter(|pos| self.eq_ignoring_ply(pos))
+            .count()
+    }
+
     /// 手番側が敵玉を取れる状態かどうかを返す。
     pub fn can_capture_king(&self) -> bool {
         let sq = ai::find_king_sq(&self.board, self.side.inv()).unwrap();
@@ -291,8 +401,18 @@ impl Position {
 
                 self.board[src] = BoardCell::Empty;
                 self.board[dst] = BoardCell::from_side_pt(self.side, pt_dst);
+                self.xor_zobrist_board(self.side, pt_src, src);
+                self.xor_zobrist_board(self.side, pt_dst, dst);
                 if let Some(pt) = pt_capture {
-                    self.hands[self.side][pt.to_raw()] += 1;
+                    self.xor_zobrist_board(self.side.inv(), pt, dst);
+                    self.add_hand(self.side, pt.to_raw(), 1);
+                }
+                if pt_src == Piece::Pawn {
+                    self.update_pawn_mask(self.side, src.x().get());
+                    self.update_pawn_mask(self.side, dst.x().get());
+                }
+                if pt_capture == Some(Piece::Pawn) {
+                    self.update_pawn_mask(self.side.inv(), dst.x().get());
                 }
 
                 MoveCmd::nondrop(src, dst, is_promotion, pt_capture)
@@ -302,7 +422,7 @@ impl Position {
                 let dst = drop.dst;
 
                 chk!(
-                    self.hand(self.side)[pt] > 0,
+                    self.hand(self.side).get(pt).copied().unwrap_or(0) > 0,
                     Error::illegal_move(mv, "not in hand")
                 );
                 chk!(
@@ -311,19 +431,55 @@ impl Position {
                 );
 
                 self.board[dst] = BoardCell::from_side_pt(self.side, pt);
-                self.hands[self.side][pt] -= 1;
+                self.xor_zobrist_board(self.side, pt, dst);
+                self.add_hand(self.side, pt, -1);
+                if pt == Piece::Pawn {
+                    self.pawn_masks[self.side].set(dst.x().get());
+                }
 
                 MoveCmd::drop(pt, dst)
             }
         };
 
         self.side.toggle();
+        self.zobrist ^= ZOBRIST.side;
         self.ply += 1;
 
         Ok(mv_cmd)
     }
 
     /// mv_cmd の中身はある程度信用している。
+    /// do_move() に加え、取った駒、成、王手、詰みを調べて返す。
+    /// 詰みの判定は合法手生成を伴うので do_move() よりかなり重い。
+    pub fn do_move_annotated(&mut self, mv: &Move) -> Result<(MoveCmd, MoveAnnotation)> {
+        let mv_cmd = self.do_move(mv)?;
+
+        let is_promotion = matches!(mv, Move::Nondrop(nondrop) if nondrop.is_promotion);
+        let is_check = self.is_check();
+        let is_mate = is_check && evasion_count(self) == 0;
+
+        let annot = MoveAnnotation {
+            pt_capture: mv_cmd.pt_capture(),
+            is_promotion,
+            is_check,
+            is_mate,
+        };
+
+        Ok((mv_cmd, annot))
+    }
+
+    /// side 側の玉に相手の利きがある (王手が掛かっている) かどうかを返す。
+    /// 玉が盤上にない異常局面では false を返す。
+    pub fn in_check(&self, side: Side) -> bool {
+        let sq = unwrap_or_return!(ai::find_king_sq(&self.board, side), false);
+        effect::iter_effects(&self.board, side.inv()).any(|(_, dst)| sq == dst)
+    }
+
+    /// 手番側の玉に王手が掛かっているかどうかを返す。in_check() を参照。
+    pub fn is_check(&self) -> bool {
+        self.in_check(self.side)
+    }
+
     pub fn undo_move(&mut self, mv_cmd: &MoveCmd) -> Result<()> {
         let opponent = self.side.inv();
 
@@ -354,12 +510,22 @@ impl Position {
                 }
 
                 self.board[src] = BoardCell::from_side_pt(opponent, pt_src);
+                self.xor_zobrist_board(opponent, pt_src, src);
+                self.xor_zobrist_board(opponent, pt_dst, dst);
                 if let Some(pt) = pt_capture {
                     self.board[dst] = BoardCell::from_side_pt(self.side, pt);
-                    self.hands[opponent][pt.to_raw()] -= 1;
+                    self.xor_zobrist_board(self.side, pt, dst);
+                    self.add_hand(opponent, pt.to_raw(), -1);
                 } else {
                     self.board[dst] = BoardCell::Empty;
                 }
+                if pt_src == Piece::Pawn {
+                    self.update_pawn_mask(opponent, src.x().get());
+                    self.update_pawn_mask(opponent, dst.x().get());
+                }
+                if pt_capture == Some(Piece::Pawn) {
+                    self.pawn_masks[self.side].set(dst.x().get());
+                }
             }
             MoveCmd::Drop(drop) => {
                 let pt = drop.pt;
@@ -371,11 +537,16 @@ impl Position {
                 );
 
                 self.board[dst] = BoardCell::Empty;
-                self.hands[opponent][pt] += 1;
+                self.xor_zobrist_board(opponent, pt, dst);
+                self.add_hand(opponent, pt, 1);
+                if pt == Piece::Pawn {
+                    self.update_pawn_mask(opponent, dst.x().get());
+                }
             }
         }
 
         self.side.toggle();
+        self.zobrist ^= ZOBRIST.side;
         self.ply -= 1;
 
         Ok(())
@@ -384,4 +555,802 @@ impl Position {
     pub fn to_sfen(&self) -> String {
         sfen::position_to_sfen(self).into_owned()
     }
+
+    /// 局面の Zobrist ハッシュを返す。手番、盤面、持駒を含み、手数は含まない。
+    /// do_move(), undo_move() で差分更新されるので盤面を走査しない。
+    pub fn zobrist(&self) -> u64 {
+        self.zobrist
+    }
+
+    /// Zobrist ハッシュを局面全体から計算する。
+    fn compute_zobrist(&self) -> u64 {
+        let table = &*ZOBRIST;
+
+        let mut key = if self.side.is_gote() { table.side } else { 0 };
+
+        for sq in Sq::iter_valid() {
+            match self.board[sq] {
+                BoardCell::Sente(pt) => key ^= table.board(Side::Sente, pt, sq),
+                BoardCell::Gote(pt) => key ^= table.board(Side::Gote, pt, sq),
+                _ => {}
+            }
+        }
+
+        for side in Side::iter() {
+            for pt in Piece::iter_hand() {
+                key ^= table.hand(side, pt, self.hands[side][pt]);
+            }
+        }
+
+        key
+    }
+
+    /// 盤面を左右反転した局面を返す。手番、持駒、手数はそのまま。
+    /// 駒の動きは左右対称なので、反転前後の局面は本質的に同等。
+    pub fn mirror(&self) -> Self {
+        Self::new(self.side, self.board.mirror(), self.hands.clone(), self.ply)
+    }
+
+    /// 盤面を 180 度回し、駒・持駒・手番の先後を入れ替えた局面を返す。手数はそのまま。
+    /// 先後対称性のテストなどに使える。
+    pub fn inv(&self) -> Self {
+        Self::new(
+            self.side.inv(),
+            self.board.inv(),
+            self.hands.inv(),
+            self.ply,
+        )
+    }
+
+    /// 左右反転に関して正規化したハッシュを返す。
+    /// 局面とその左右反転のうち Zobrist ハッシュが小さい方を採用するので、
+    /// 左右反転の関係にある局面同士は同じ値となる。
+    pub fn canonical_key(&self) -> u64 {
+        std::cmp::min(self.zobrist(), self.mirror().zobrist())
+    }
+}
+
+//--------------------------------------------------------------------
+// Zobrist ハッシュ
+//--------------------------------------------------------------------
+
+/// 持駒の枚数として扱う上限 (これを超える枚数は剰余をとる)
+const ZOBRIST_HAND_COUNT: usize = 32;
+
+struct ZobristTable {
+    side: u64, // 後手番のとき XOR する
+    board: Vec<u64>,
+    hand: Vec<u64>,
+}
+
+impl ZobristTable {
+    fn new() -> Self {
+        use rand::SeedableRng;
+
+        // ハッシュ値が実行ごとに変わらないよう、シードを固定する
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x6e61_6974_6f75);
+
+        let side = rng.gen();
+        let board = (0..2 * 14 * 11 * 11).map(|_| rng.gen()).collect();
+        let hand = (0..2 * 7 * ZOBRIST_HAND_COUNT)
+            .map(|i| {
+                // 枚数 0 は XOR しないのと同じにしておく
+                if i % ZOBRIST_HAND_COUNT == 0 {
+                    0
+                } else {
+                    rng.gen()
+                }
+            })
+            .collect();
+
+        Self { side, board, hand }
+    }
+
+    fn board(&self, side: Side, pt: Piece, sq: Sq) -> u64 {
+        self.board[(side as usize * 14 + pt as usize) * 11 * 11 + sq.get() as usize]
+    }
+
+    fn hand(&self, side: Side, pt: Piece, count: u8) -> u64 {
+        let count = usize::from(count) % ZOBRIST_HAND_COUNT;
+        self.hand[(side as usize * 7 + pt as usize) * ZOBRIST_HAND_COUNT + count]
+    }
+}
+
+static ZOBRIST: Lazy<ZobristTable> = Lazy::new(ZobristTable::new);
+
+//--------------------------------------------------------------------
+// 局面履歴 (千日手判定用)
+//--------------------------------------------------------------------
+
+/// 千日手となる同一局面の出現回数
+pub const SENNICHITE_COUNT: usize = 4;
+
+/// 局面の履歴を Zobrist ハッシュ列として保持する。
+/// 手番と持駒を含めた同一局面の反復を数えるのに使う (手数は無視)。
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct PositionHistory {
+    keys: Vec<u64>,
+}
+
+impl PositionHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// pos を最初の局面とする履歴を作る。
+    pub fn from_position(pos: &Position) -> Self {
+        Self {
+            keys: vec![pos.zobrist()],
+        }
+    }
+
+    /// 局面を履歴の末尾に追加する。これが現局面となる。
+    pub fn push(&mut self, pos: &Position) {
+        self.keys.push(pos.zobrist());
+    }
+
+    /// 現局面を履歴から取り除き、そのハッシュを返す。
+    pub fn pop(&mut self) -> Option<u64> {
+        self.keys.pop()
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// 現局面が過去に出現した回数を返す (現局面自身は数えない)。履歴が空なら 0。
+    pub fn count_repetition(&self) -> usize {
+        let (cur, past) = unwrap_or_return!(self.keys.split_last(), 0);
+        past.iter().filter(|&key| key == cur).count()
+    }
+
+    /// 現局面が SENNICHITE_COUNT 回目の出現 (千日手) かどうかを返す。
+    /// 連続王手の千日手は区別しない。
+    pub fn is_sennichite(&self) -> bool {
+        !self.is_empty() && self.count_repetition() + 1 >= SENNICHITE_COUNT
+    }
+}
+
+//--------------------------------------------------------------------
+// 指し手の一時適用
+//--------------------------------------------------------------------
+
+/// pos に mv を適用して f を呼び出し、その結果を返す。
+/// f から戻る際(パニック時も含む)には必ず mv を取り消す。
+///
+/// mv の適用に失敗した場合は f を呼ばずにエラーを返す。
+pub fn with_move<R>(
+    pos: &mut Position,
+    mv: &Move,
+    f: impl FnOnce(&mut Position) -> R,
+) -> Result<R> {
+    struct Guard<'a> {
+        pos: &'a mut Position,
+        cmd: MoveCmd,
+    }
+    impl Drop for Guard<'_> {
+        fn drop(&mut self) {
+            self.pos.undo_move(&self.cmd).unwrap();
+        }
+    }
+
+    let cmd = pos.do_move(mv)?;
+    let guard = Guard { pos, cmd };
+
+    Ok(f(guard.pos))
+}
+
+//--------------------------------------------------------------------
+// テスト用ユーティリティ
+//
+// test-util feature を有効にすると外部クレートからも使える。
+//--------------------------------------------------------------------
+
+/// pos に mv を適用して戻したとき、pos が元と完全に一致することを確かめる。
+/// また、適用後の差分更新された Zobrist ハッシュが局面全体から計算したものと一致することも確かめる。
+/// いずれかが成り立たなければ panic する。mv は pos において適用可能でなければならない。
+#[cfg(any(test, feature = "test-util"))]
+pub fn assert_move_roundtrip(pos: &mut Position, mv: &Move) {
+    let pos_orig = pos.clone();
+
+    let cmd = pos.do_move(mv).unwrap();
+    assert_eq!(
+        pos.zobrist(),
+        pos.compute_zobrist(),
+        "zobrist mismatch: {:?}",
+        mv
+    );
+    pos.undo_move(&cmd).unwrap();
+
+    assert_eq!(*pos, pos_orig, "move roundtrip failed: {:?}", mv);
+}
+
+/// ランダム局面からランダムな疑似合法手を最大 max_ply 手指し、全て戻す。これを n_iter 回行う。
+/// 各手の適用/巻き戻しが局面 (Zobrist ハッシュを含む) を正しく復元すること、
+/// および差分更新された Zobrist ハッシュが正しいことを確かめる。
+#[cfg(any(test, feature = "test-util"))]
+pub fn fuzz_move_roundtrip(rng: &mut impl Rng, n_iter: usize, max_ply: usize) {
+    use rand::seq::SliceRandom;
+
+    for _ in 0..n_iter {
+        let mut pos = Position::random(rng);
+
+        let mut history = Vec::new();
+        for _ in 0..max_ply {
+            // 玉を取る手は適用できない
+            if pos.can_capture_king() {
+                break;
+            }
+            let mvs: Vec<_> = your_move::moves_pseudo_legal(&pos).collect();
+            let mv = match mvs.choose(rng) {
+                Some(mv) => mv.clone(),
+                None => break,
+            };
+            assert_move_roundtrip(&mut pos, &mv);
+            history.push((pos.clone(), pos.do_move(&mv).unwrap()));
+        }
+        while let Some((pos_before, cmd)) = history.pop() {
+            pos.undo_move(&cmd).unwrap();
+            assert_eq!(pos, pos_before);
+        }
+    }
+}
+
+//--------------------------------------------------------------------
+// 駒打ちの可否
+//--------------------------------------------------------------------
+
+/// 手番側が持駒 pt を dst に打てるかどうかを返す。
+/// 持駒の有無、移動先が空白か、行きどころのない駒、二歩を調べる。自殺手かどうかは調べない。
+///
+/// allow_drop_pawn_mate が false なら打ち歩詰めも不可とする。
+pub fn can_drop(pos: &Position, pt: Piece, dst: Sq, allow_drop_pawn_mate: bool) -> bool {
+    let side = pos.side();
+
+    if pos.hand(side).get(pt).copied().unwrap_or(0) == 0 {
+        return false;
+    }
+
+    if !can_drop_on_board(pos.board(), side, pos.pawn_mask(side), pt, dst) {
+        return false;
+    }
+
+    if !allow_drop_pawn_mate && matches!(pt, Piece::Pawn) && is_drop_pawn_mate(pos, dst) {
+        return false;
+    }
+
+    true
+}
+
+/// 持駒の有無を除いて、side 側が pt を dst に打てるかどうかを返す。
+/// 移動先が空白か、行きどころのない駒、二歩を調べる。
+/// pawn_mask は board 上の side 側の歩の筋。指し手生成で使い回すため引数で受け取る。
+pub fn can_drop_on_board(
+    board: &Board,
+    side: Side,
+    pawn_mask: &PawnMask,
+    pt: Piece,
+    dst: Sq,
+) -> bool {
+    check_drop_on_board(board, side, pawn_mask, pt, dst).is_ok()
+}
+
+/// 駒を打てない理由
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DropError {
+    Occupied,  // 移動先が空白でない
+    DeadPiece, // 行きどころのない駒
+    Nifu,      // 二歩
+}
+
+/// can_drop_on_board() と同じ判定を行い、打てない場合はその理由を返す。
+pub fn check_drop_on_board(
+    board: &Board,
+    side: Side,
+    pawn_mask: &PawnMask,
+    pt: Piece,
+    dst: Sq,
+) -> std::result::Result<(), DropError> {
+    // 移動先が空白でないならNG
+    if !board[dst].is_empty() {
+        return Err(DropError::Occupied);
+    }
+
+    // 行きどころのない駒はNG
+    if !dst.can_put(side, pt) {
+        return Err(DropError::DeadPiece);
+    }
+
+    // 二歩はNG
+    if matches!(pt, Piece::Pawn) && pawn_mask.test(dst.x().get()) {
+        return Err(DropError::Nifu);
+    }
+
+    Ok(())
+}
+
+/// 全ての valid なマスについて、手番側が pt を打てるかどうかとその理由を昇順に列挙する。
+/// 持駒の有無と打ち歩詰めは調べない。
+pub fn drop_legality_map(
+    pos: &Position,
+    pt: Piece,
+) -> Vec<(Sq, std::result::Result<(), DropError>)> {
+    let side = pos.side();
+
+    Sq::iter_valid()
+        .map(|dst| {
+            let res = check_drop_on_board(pos.board(), side, pos.pawn_mask(side), pt, dst);
+            (dst, res)
+        })
+        .collect()
+}
+
+/// 手番側が dst に歩を打つと打ち歩詰めになるかどうかを返す。
+/// 歩を打てることは仮定している。
+fn is_drop_pawn_mate(pos: &Position, dst: Sq) -> bool {
+    let mut pos = pos.clone();
+    with_move(&mut pos, &Move::drop(Piece::Pawn, dst), |pos| {
+        let side = pos.side();
+        let sq_king = unwrap_or_return!(ai::find_king_sq(pos.board(), side), false);
+        let is_check = effect::iter_effects(pos.board(), side.inv()).any(|(_, dst)| dst == sq_king);
+        is_check && your_move::moves_legal(pos).next().is_none()
+    })
+    .unwrap()
+}
+
+//--------------------------------------------------------------------
+// 合法手
+//--------------------------------------------------------------------
+
+/// 手番側の合法手 (自殺手を除く) を列挙する。
+/// allow_drop_pawn_mate が false なら打ち歩詰めも除く。
+///
+/// 疑似合法手を全て実際に指して調べるので遅い。思考ルーチンでは使われない。
+pub fn legal_moves(pos: &mut Position, allow_drop_pawn_mate: bool) -> Vec<Move> {
+    let mut mvs: Vec<_> = your_move::moves_pseudo_legal(pos).collect();
+
+    mvs.retain(|mv| with_move(pos, mv, |pos| !pos.can_capture_king()).unwrap());
+
+    // 打ち歩詰めの判定は相手の合法手生成を伴うので、自殺手を除いた後に行う
+    if !allow_drop_pawn_mate {
+        mvs.retain(|mv| match mv {
+            Move::Drop(drop) if drop.pt() == Piece::Pawn => {
+                can_drop(pos, drop.pt(), drop.dst(), false)
+            }
+            _ => true,
+        });
+    }
+
+    mvs
+}
+
+/// pos から depth 手先までの合法手 (legal_moves() で打ち歩詰めを除いたもの) の末端局面数を返す (perft)。
+/// 指し手生成の検証用。depth が 0 なら 1。
+pub fn perft(pos: &mut Position, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mvs = legal_moves(pos, false);
+    if depth == 1 {
+        return mvs.len() as u64;
+    }
+
+    mvs.iter()
+        .map(|mv| with_move(pos, mv, |pos| perft(pos, depth - 1)).unwrap())
+        .sum()
+}
+
+/// perft() の初手ごとの内訳を返す。回帰調査用。
+pub fn perft_divide(pos: &mut Position, depth: u32) -> Vec<(Move, u64)> {
+    assert!(depth >= 1);
+
+    legal_moves(pos, false)
+        .into_iter()
+        .map(|mv| {
+            let n = with_move(pos, &mv, |pos| perft(pos, depth - 1)).unwrap();
+            (mv, n)
+        })
+        .collect()
+}
+
+//--------------------------------------------------------------------
+// 駒の取り合い
+//--------------------------------------------------------------------
+
+/// sq 上の相手駒を手番側が取りに行ったときの駒の取り合いの損得を返す (SEE)。
+/// 互いに最も安い駒から取り合い、各時点で取り合いを打ち切る方が得ならそこで打ち切るものとする。
+/// 駒価値は PRICES_0 による。成りは考慮しない。
+///
+/// 1 手取るごとに盤面から利きを計算し直すので、背後からの利きも考慮される。
+/// sq に相手駒がないか、手番側の利きがない場合は 0 を返す。
+pub fn static_exchange(pos: &Position, sq: Sq) -> i32 {
+    /// board 上で sq に利いている side 側の駒のうち最も安いものを (src, pt) で返す。
+    fn cheapest_attacker(board: &Board, side: Side, sq: Sq) -> Option<(Sq, Piece)> {
+        effect::iter_effects(board, side)
+            .filter(|&(_, dst)| dst == sq)
+            .map(|(src, _)| (src, board[src].piece_of(side).unwrap()))
+            .min_by_key(|&(_, pt)| PRICES_0[pt])
+    }
+
+    let mut side = pos.side();
+    let mut pt_target = unwrap_or_return!(pos.board()[sq].piece_of(side.inv()), 0);
+
+    let mut board = pos.board().clone();
+    let mut gains = Vec::new();
+    while let Some((src, pt)) = cheapest_attacker(&board, side, sq) {
+        // 取った駒の価値から、それまでの相手の獲得分を引く
+        let prev = gains.last().copied().unwrap_or(0);
+        gains.push(i32::from(PRICES_0[pt_target]) - prev);
+
+        board[src] = BoardCell::Empty;
+        board[sq] = BoardCell::from_side_pt(side, pt);
+        pt_target = pt;
+        side = side.inv();
+    }
+
+    // 末尾から、取り合いを打ち切った方が得かどうかを反映していく
+    while gains.len() >= 2 {
+        let last = gains.pop().unwrap();
+        let prev = gains.last_mut().unwrap();
+        *prev = -std::cmp::max(-*prev, last);
+    }
+
+    gains.first().copied().unwrap_or(0)
+}
+
+//--------------------------------------------------------------------
+// 玉の逃げ道
+//--------------------------------------------------------------------
+
+/// side 側の玉が移動できるマスたちを返す。
+/// 移動先は空白または相手駒のマスで、かつ相手の利きがないものに限る。
+/// 玉が盤上にない場合、空の Vec を返す。
+pub fn king_escape_squares(pos: &Position, side: Side) -> Vec<Sq> {
+    let sq_king = unwrap_or_return!(ai::find_king_sq(pos.board(), side), Vec::new());
+
+    // 玉自身が相手の遠隔利きを遮らないよう、玉を取り除いた盤面で利きを計算する
+    let mut board = pos.board().clone();
+    board[sq_king] = BoardCell::Empty;
+    let eff_board = EffectBoard::from_board(&board, side);
+
+    effect::piece_effects_melee(side, Piece::King)
+        .map(|di| sq_king + di)
+        .filter(|&dst| {
+            let cell = pos.board()[dst];
+            (cell.is_empty() || cell.is_side(side.inv())) && eff_board[dst][side.inv()].count() == 0
+        })
+        .collect()
+}
+
+/// 手番側の合法手 (自殺手、打ち歩詰めを除く) の数を返す。
+/// 手番側が王手されていれば、これは王手回避手の数であり、0 なら詰み。
+/// 詰みへの近さ (逃げ道の少なさ) の目安になる。
+pub fn evasion_count(pos: &mut Position) -> usize {
+    your_move::moves_with_policy(pos, your_move::IllegalPolicy::Strict).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_static_exchange() {
+        // 5五の歩に飛車が利いているだけ: 歩をタダ取り
+        let pos = Position::from_sfen("sfen k8/9/9/9/4p4/9/9/9/K3R4 b - 1").unwrap();
+        assert_eq!(static_exchange(&pos, Sq::from_xy(5, 5)), 1);
+
+        // 歩に金の紐がついている: 飛車で取ると飛車を取り返される
+        let pos = Position::from_sfen("sfen k8/9/9/4g4/4p4/9/9/9/K3R4 b - 1").unwrap();
+        assert_eq!(static_exchange(&pos, Sq::from_xy(5, 5)), 1 - 17);
+
+        // 歩に金の紐がついているが、銀で取れば金で取り返されても飛車で取り返せる
+        let pos = Position::from_sfen("sfen k8/9/9/4g4/4p4/4S4/9/9/K3R4 b - 1").unwrap();
+        assert_eq!(static_exchange(&pos, Sq::from_xy(5, 5)), 1 - 8 + 8);
+
+        // 相手駒がない、または利きがない
+        let pos = Position::from_sfen("sfen k8/9/9/9/4p4/9/9/9/K8 b - 1").unwrap();
+        assert_eq!(static_exchange(&pos, Sq::from_xy(5, 5)), 0);
+        assert_eq!(static_exchange(&pos, Sq::from_xy(4, 4)), 0);
+    }
+
+    #[test]
+    fn test_canonical_key() {
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..100 {
+            let pos = Position::random(&mut rng);
+            let pos_mirror = pos.mirror();
+
+            assert_eq!(pos_mirror.mirror(), pos);
+            assert_eq!(pos.canonical_key(), pos_mirror.canonical_key());
+        }
+
+        // 手番が違えば別の局面
+        let pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/2G1K4 b - 1").unwrap();
+        let pos_gote = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/2G1K4 w - 1").unwrap();
+        assert_ne!(pos.canonical_key(), pos_gote.canonical_key());
+
+        // 金を左右反転した位置に置いた局面は同じ key を持つ
+        let pos_mirror = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/4K1G2 b - 1").unwrap();
+        assert_eq!(pos.canonical_key(), pos_mirror.canonical_key());
+        assert_ne!(pos.zobrist(), pos_mirror.zobrist());
+    }
+
+    #[test]
+    fn test_piece_at() {
+        let pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/9/9/2G1K4 b - 1").unwrap();
+
+        assert_eq!(
+            pos.piece_at(Sq::from_xy(3, 9)),
+            Some((Side::Sente, Piece::Gold))
+        );
+        assert_eq!(
+            pos.piece_at(Sq::from_xy(5, 1)),
+            Some((Side::Gote, Piece::King))
+        );
+        assert_eq!(pos.piece_at(Sq::from_xy(5, 5)), None);
+        assert_eq!(pos.side_at(Sq::from_xy(5, 1)), Some(Side::Gote));
+        assert!(pos.is_empty(Sq::from_xy(5, 5)));
+        assert!(!pos.is_empty(Sq::from_xy(0, 0)));
+
+        assert_eq!(pos.king(Side::Sente), Some(Sq::from_xy(5, 9)));
+        assert_eq!(pos.king(Side::Gote), Some(Sq::from_xy(5, 1)));
+    }
+
+    #[test]
+    fn test_do_move_annotated() {
+        // 頭金で詰み
+        let mut pos = Position::from_sfen("sfen 4k4/9/4P4/9/9/9/9/9/4K4 b G 1").unwrap();
+        let (cmd, annot) = pos
+            .do_move_annotated(&Move::drop(Piece::Gold, Sq::from_xy(5, 2)))
+            .unwrap();
+        assert_eq!(
+            annot,
+            MoveAnnotation {
+                pt_capture: None,
+                is_promotion: false,
+                is_check: true,
+                is_mate: true,
+            }
+        );
+        pos.undo_move(&cmd).unwrap();
+
+        // 王手だが詰みではない
+        let (_, annot) = pos
+            .do_move_annotated(&Move::drop(Piece::Gold, Sq::from_xy(4, 2)))
+            .unwrap();
+        assert!(annot.is_check && !annot.is_mate);
+
+        // 駒を取って成る (王手ではない)
+        let mut pos = Position::from_sfen("sfen 4k4/9/5p3/9/7B1/9/9/9/4K4 b - 1").unwrap();
+        let (_, annot) = pos
+            .do_move_annotated(&Move::nondrop(Sq::from_xy(8, 5), Sq::from_xy(6, 3), true))
+            .unwrap();
+        assert_eq!(
+            annot,
+            MoveAnnotation {
+                pt_capture: Some(Piece::Pawn),
+                is_promotion: true,
+                is_check: false,
+                is_mate: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_drop_legality_map() {
+        let pos = Position::from_sfen("sfen 4k4/9/9/9/9/9/4P4/9/4K4 b P 1").unwrap();
+        let map: HashMap<_, _> = drop_legality_map(&pos, Piece::Pawn).into_iter().collect();
+
+        assert_eq!(map.len(), 81);
+        assert_eq!(map[&Sq::from_xy(5, 1)], Err(DropError::Occupied));
+        assert_eq!(map[&Sq::from_xy(1, 1)], Err(DropError::DeadPiece));
+        assert_eq!(map[&Sq::from_xy(5, 5)], Err(DropError::Nifu));
+        assert_eq!(map[&Sq::from_xy(4, 5)], Ok(()));
+
+        // 盤上の判定は生成器と一致する
+        let n_ok = map.values().filter(|res| res.is_ok()).count();
+        let n_drop = your_move::moves_pseudo_legal(&pos)
+            .filter(Move::is_drop)
+            .count();
+        assert_eq!(n_ok, n_drop);
+    }
+
+    #[test]
+    fn test_evasion_count() {
+        // 頭金で詰み
+        let mut pos = Position::from_sfen("sfen 4k4/4G4/4P4/9/9/9/9/9/4K4 w - 1").unwrap();
+        assert_eq!(evasion_count(&mut pos), 0);
+
+        // 1 一玉に 1 九飛の王手: 持駒がないので 2 一、2 二へ逃げる 2 通りのみ
+        let mut pos = Position::from_sfen("sfen 8k/9/9/9/9/9/9/9/K7R w - 1").unwrap();
+        assert_eq!(evasion_count(&mut pos), 2);
+    }
+
+    #[test]
+    fn test_move_roundtrip() {
+        let mut rng = rand::thread_rng();
+        fuzz_move_roundtrip(&mut rng, 100, 30);
+    }
+
+    #[test]
+    fn test_pawn_mask_incremental() {
+        use rand::seq::SliceRandom;
+
+        fn check(pos: &Position) {
+            for side in Side::iter() {
+                assert_eq!(
+                    *pos.pawn_mask(side),
+                    PawnMask::from_board_side(pos.board(), side)
+                );
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..100 {
+            let mut pos = Position::random(&mut rng);
+            check(&pos);
+
+            let mut cmds = Vec::new();
+            for _ in 0..30 {
+                // 玉を取る手は適用できない
+                if pos.can_capture_king() {
+                    break;
+                }
+                let mvs: Vec<_> = your_move::moves_pseudo_legal(&pos).collect();
+                let mv = match mvs.choose(&mut rng) {
+                    Some(mv) => mv,
+                    None => break,
+                };
+                cmds.push(pos.do_move(mv).unwrap());
+                check(&pos);
+            }
+            while let Some(cmd) = cmds.pop() {
+                pos.undo_move(&cmd).unwrap();
+                check(&pos);
+            }
+        }
+    }
+
+    #[test]
+    fn test_zobrist_sfen() {
+        // SFEN 往復しても同一局面なら同一ハッシュ (差分更新は fuzz_move_roundtrip() で確かめる)
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let pos = Position::random(&mut rng);
+            assert_eq!(pos.zobrist(), pos.compute_zobrist());
+            assert_eq!(
+                Position::from_sfen(pos.to_sfen()).unwrap().zobrist(),
+                pos.zobrist()
+            );
+        }
+    }
+
+    #[test]
+    fn test_position_history() {
+        let mut pos = Position::from_sfen(sfen::SFEN_HIRATE).unwrap();
+        let mut history = PositionHistory::from_position(&pos);
+        assert_eq!(history.count_repetition(), 0);
+
+        // 互いに玉を往復させると初期局面が繰り返される
+        let mvs: Vec<_> = ["5i5h", "5a5b", "5h5i", "5b5a"]
+            .iter()
+            .map(|sfen| Move::from_sfen(sfen).unwrap())
+            .collect();
+        for i in 1..=3 {
+            for mv in &mvs {
+                assert!(!history.is_sennichite());
+                pos.do_move(mv).unwrap();
+                history.push(&pos);
+            }
+            assert_eq!(history.count_repetition(), i);
+        }
+        assert!(history.is_sennichite());
+
+        // 1 手戻した局面はまだ 3 回目の出現
+        history.pop();
+        assert_eq!(history.count_repetition(), 2);
+        assert!(!history.is_sennichite());
+
+        assert!(!PositionHistory::new().is_sennichite());
+    }
+
+    #[test]
+    fn test_in_check() {
+        let pos = Position::from_sfen(sfen::SFEN_HIRATE).unwrap();
+        assert!(!pos.is_check());
+        assert!(!pos.in_check(Side::Gote));
+
+        // 後手玉に王手 (手番は後手)
+        let pos = Position::from_sfen("sfen 4k4/9/4P4/9/9/9/9/9/4K4 w G 1").unwrap();
+        assert!(!pos.is_check());
+        let pos = Position::from_sfen("sfen 4k4/4G4/9/9/9/9/9/9/4K4 w - 1").unwrap();
+        assert!(pos.is_check());
+        assert!(pos.in_check(Side::Gote));
+        assert!(!pos.in_check(Side::Sente));
+
+        // 玉がなければ false
+        let pos = Position::from_sfen("sfen 9/4G4/9/9/9/9/9/9/4K4 w - 1").unwrap();
+        assert!(!pos.in_check(Side::Gote));
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        use std::collections::HashSet;
+
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..100 {
+            let mut pos = Position::random(&mut rng);
+            // 相手玉を取れる局面は除く
+            if pos.can_capture_king() {
+                continue;
+            }
+
+            let actual: HashSet<_> = legal_moves(&mut pos, true).into_iter().collect();
+            let expected: HashSet<_> = your_move::moves_legal(&mut pos).collect();
+            assert_eq!(actual, expected);
+
+            let actual: HashSet<_> = legal_moves(&mut pos, false).into_iter().collect();
+            let expected: HashSet<_> =
+                your_move::moves_with_policy(&mut pos, your_move::IllegalPolicy::Strict)
+                    .into_iter()
+                    .collect();
+            assert_eq!(actual, expected);
+        }
+
+        // 打ち歩詰め
+        let mut pos = Position::from_sfen("sfen 3lkl3/9/4G4/9/9/9/9/9/4K4 b P 1").unwrap();
+        let mv = Move::drop(Piece::Pawn, Sq::from_xy(5, 2));
+        assert!(legal_moves(&mut pos, true).contains(&mv));
+        assert!(!legal_moves(&mut pos, false).contains(&mv));
+    }
+
+    #[test]
+    fn test_perft() {
+        let mut pos = Position::from_sfen(sfen::SFEN_HIRATE).unwrap();
+
+        // 浅い手数では本作の合法手は通常の将棋と一致する
+        for (depth, expected) in [(0, 1), (1, 30), (2, 900), (3, 25470)] {
+            assert_eq!(perft(&mut pos, depth), expected);
+        }
+
+        let divide = perft_divide(&mut pos, 2);
+        assert_eq!(divide.len(), 30);
+        assert!(divide.iter().all(|&(_, n)| n == 30));
+    }
+
+    // debug ビルドでは時間がかかるので、既定では実行しない (--include-ignored で実行)
+    #[test]
+    #[ignore]
+    fn test_perft_deep() {
+        let mut pos = Position::from_sfen(sfen::SFEN_HIRATE).unwrap();
+        assert_eq!(perft(&mut pos, 4), 719731);
+    }
+
+    #[test]
+    fn test_inv() {
+        let pos = Position::from_sfen("sfen 4k4/9/4P4/9/9/9/9/7r1/4K4 b Gp 5").unwrap();
+        let expected = Position::from_sfen("sfen 4k4/1R7/9/9/9/9/4p4/9/4K4 w Pg 5").unwrap();
+        assert_eq!(pos.inv(), expected);
+        assert_eq!(expected.inv(), pos);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let pos = Position::random(&mut rng);
+            assert_eq!(pos.inv().inv(), pos);
+            assert_eq!(
+                pos.inv().board().census()[Side::Sente],
+                pos.board().census()[Side::Gote]
+            );
+        }
+    }
 }
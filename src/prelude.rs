@@ -1,6 +1,6 @@
 pub use crate::position::Position;
 pub use crate::pretty::Pretty;
 pub use crate::{
-    can_promote, Board, BoardCell, Hand, Handicap, Hands, Move, MoveDrop, MoveNondrop, Piece, Side,
-    Sq, SqX, SqY, SQ_INVALID,
+    can_promote, Board, BoardCell, Hand, Handicap, Hands, Move, MoveDrop, MoveKind, MoveNondrop,
+    Piece, Side, SideArray, Sq, SqX, SqY, SQ_INVALID,
 };
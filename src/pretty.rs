@@ -231,3 +231,60 @@ impl Pretty for PawnMask {
         format!("[{}]", (1..=9).filter(|&x| self.test(x)).join(", ")).into()
     }
 }
+
+/// 筋・段の見出し付きで局面を表示する。
+///
+/// last_move のマスの駒は `[歩]` のように括弧で囲む。
+/// show_check が true で手番側の玉に王手が掛かっていれば、その玉に `*` を付ける。
+/// 後手の駒には従来通り `v` を付ける。
+///
+/// ```
+/// # use naitou_clone::prelude::*;
+/// # use naitou_clone::pretty::pretty_labeled;
+/// let pos = Position::from_sfen("sfen 4k4/4G4/4P4/9/9/9/9/9/4K4 w - 2").unwrap();
+/// let s = pretty_labeled(&pos, Some(Sq::from_xy(5, 2)), true);
+/// assert!(s.contains("[ 金]"));
+/// assert!(s.contains("*v玉 "));
+/// ```
+pub fn pretty_labeled(pos: &Position, last_move: Option<Sq>, show_check: bool) -> String {
+    let board = pos.board();
+
+    let sq_checked = if show_check && pos.is_check() {
+        crate::ai::find_king_sq(board, pos.side())
+    } else {
+        None
+    };
+
+    let mut res = String::new();
+
+    res.push_str(&format!("後手持駒:{}\n", pos.hand(Side::Gote).pretty()));
+
+    for x in 1..=9 {
+        res.push_str(&format!("  {} ", SqX::new(x).pretty()));
+    }
+    res.push('\n');
+
+    for y in 1..=9 {
+        for x in 1..=9 {
+            let sq = Sq::from_xy(x, y);
+            let (open, close) = if last_move == Some(sq) {
+                ('[', ']')
+            } else if sq_checked == Some(sq) {
+                ('*', ' ')
+            } else {
+                (' ', ' ')
+            };
+            let (side_mark, pt_str) = match board[sq] {
+                BoardCell::Sente(pt) => (' ', pt.pretty()),
+                BoardCell::Gote(pt) => ('v', pt.pretty()),
+                _ => (' ', "口".into()),
+            };
+            res.push_str(&format!("{}{}{}{}", open, side_mark, pt_str, close));
+        }
+        res.push_str(&format!(" {}\n", SqY::new(y).pretty()));
+    }
+
+    res.push_str(&format!("先手持駒:{}\n", pos.hand(Side::Sente).pretty()));
+
+    res
+}
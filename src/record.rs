@@ -6,8 +6,10 @@ use std::path::Path;
 
 use itertools::Itertools;
 
+use crate::ai::{self, Ai};
 use crate::prelude::*;
 use crate::sfen;
+use crate::your_move;
 use crate::{Error, Result};
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -62,11 +64,19 @@ impl Pretty for RecordEntry {
     }
 }
 
+/// 棋譜の各エントリに付随する情報。KIF などとの相互変換用で、思考ルーチンの再生には影響しない。
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MoveMeta {
+    pub time: Option<u32>,       // 消費時間 (秒)
+    pub comment: Option<String>, // コメント (複数行可)
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Record {
     handicap: Handicap,
     timelimit: bool,
     entrys: Vec<RecordEntry>,
+    metas: Vec<MoveMeta>, // entrys と同じ長さ
 }
 
 impl Record {
@@ -75,6 +85,7 @@ impl Record {
             handicap,
             timelimit,
             entrys: Vec::new(),
+            metas: Vec::new(),
         }
     }
 
@@ -84,6 +95,38 @@ impl Record {
         s.parse::<Self>()
     }
 
+    /// from_file() に加え、from_str_validated() と同様の検証を行う。
+    pub fn from_file_validated(path: impl AsRef<Path>, check_my_moves: bool) -> Result<Self> {
+        let s =
+            std::fs::read_to_string(path).map_err(|e| Error::record_parse_error(e.to_string()))?;
+        Self::from_str_validated(&s, check_my_moves)
+    }
+
+    /// 文字列から棋譜をパースし、さらに指し手を順に再生して検証する。
+    /// 疑似合法でない指し手があれば、その手数を含むエラーを返す。
+    ///
+    /// check_my_moves が true なら、my 側の指し手が思考ルーチンの指し手と一致するかも調べる。
+    pub fn from_str_validated(s: &str, check_my_moves: bool) -> Result<Self> {
+        let record = s.parse::<Self>()?;
+
+        record.positions()?;
+
+        if check_my_moves {
+            let mut ai = Ai::new(record.handicap, record.timelimit);
+            for (ply, entry) in itertools::zip(1.., &record.entrys) {
+                let mv = match entry {
+                    RecordEntry::Move(mv) | RecordEntry::MyWin(mv) => mv,
+                    RecordEntry::YourSuicide | RecordEntry::YourWin => break,
+                };
+                ai.replay_move(mv).map_err(|e| {
+                    Error::record_parse_error(format!("ply {}: {}: {}", ply, entry, e))
+                })?;
+            }
+        }
+
+        Ok(record)
+    }
+
     pub fn handicap(&self) -> Handicap {
         self.handicap
     }
@@ -96,8 +139,294 @@ impl Record {
         &self.entrys
     }
 
+    /// entrys() の各エントリに対応する付随情報を返す。
+    pub fn metas(&self) -> &[MoveMeta] {
+        &self.metas
+    }
+
+    pub fn meta_mut(&mut self, i: usize) -> &mut MoveMeta {
+        &mut self.metas[i]
+    }
+
     pub fn add(&mut self, entry: RecordEntry) {
+        self.add_with_meta(entry, MoveMeta::default());
+    }
+
+    pub fn add_with_meta(&mut self, entry: RecordEntry, meta: MoveMeta) {
         self.entrys.push(entry);
+        self.metas.push(meta);
+    }
+
+    /// 棋譜を再生し、各指し手を適用した後の局面たちを返す。開始局面は含まない。
+    /// 終局を表すエントリに達したらそこで打ち切る。
+    ///
+    /// 疑似合法でない指し手があれば、その手数を含むエラーを返す。
+    pub fn positions(&self) -> Result<Vec<Position>> {
+        let mut pos = self.handicap.initial_pos();
+        let mut res = Vec::with_capacity(self.entrys.len());
+
+        for (ply, entry) in itertools::zip(1.., &self.entrys) {
+            let mv = match entry {
+                RecordEntry::Move(mv) | RecordEntry::MyWin(mv) => mv,
+                RecordEntry::YourSuicide | RecordEntry::YourWin => break,
+            };
+
+            chk!(
+                your_move::is_pseudo_legal(&pos, mv),
+                Error::record_parse_error(format!("illegal move at ply {}: {}", ply, entry))
+            );
+            pos.do_move(mv)?;
+            res.push(pos.clone());
+        }
+
+        Ok(res)
+    }
+
+    /// 棋譜を再生し、your 側の指し手によって定跡から外れた手数 (1 始まり) を列挙する。
+    ///
+    /// 序盤処理中 (progress_level == 0) に your 側が指した直後の局面で定跡処理を試し、
+    /// 戦型が変わる(戦型変更、または定跡終了)ならその手を逸脱とみなす。
+    /// my 側の指し手が思考ルーチンと一致しなくなったらそこで打ち切る。
+    pub fn book_deviations(&self) -> Vec<usize> {
+        let mut ai = Ai::new(self.handicap, self.timelimit);
+        let mut res = Vec::new();
+
+        for (ply, entry) in itertools::zip(1.., &self.entrys) {
+            let mv = match entry {
+                RecordEntry::Move(mv) | RecordEntry::MyWin(mv) => mv,
+                RecordEntry::YourSuicide | RecordEntry::YourWin => break,
+            };
+
+            let is_your = ai.is_your_turn();
+            if ai.replay_move(mv).is_err() {
+                break;
+            }
+
+            let formation = ai.book_state().formation();
+            if is_your && ai.in_opening() {
+                let (_, formation_after) = ai.book_state().peek(ai.pos(), ai.progress_ply());
+                if formation_after != formation {
+                    res.push(ply);
+                }
+            }
+        }
+
+        res
+    }
+
+    /// 棋譜を再生し、各エントリに対してその直前の局面のルート局面評価を付けて返す。
+    /// ルート局面評価は思考ルーチンの手番のエントリにのみ付く (your 側の指し手では None)。
+    /// 評価値グラフの作成用。
+    ///
+    /// my 側の指し手が思考ルーチンと一致しなくなったら、そのエントリを含めずに打ち切る。
+    /// 終局を表すエントリは含めてから打ち切る。
+    pub fn with_evals(&self) -> Vec<(RecordEntry, Option<ai::RootEval>)> {
+        let mut ai = Ai::new(self.handicap, self.timelimit);
+        let mut res = Vec::new();
+
+        for entry in &self.entrys {
+            let root_eval = if ai.is_my_turn() {
+                Some(ai.eval_root(&ai.root_effect_board()))
+            } else {
+                None
+            };
+
+            let mv = match entry {
+                RecordEntry::Move(mv) | RecordEntry::MyWin(mv) => mv,
+                RecordEntry::YourSuicide | RecordEntry::YourWin => {
+                    res.push((entry.clone(), root_eval));
+                    break;
+                }
+            };
+
+            if ai.replay_move(mv).is_err() {
+                break;
+            }
+            res.push((entry.clone(), root_eval));
+        }
+
+        res
+    }
+
+    /// 最初の終局エントリより後ろを切り捨てた棋譜を返す。
+    pub fn canonical(&self) -> Self {
+        let len = self
+            .entrys
+            .iter()
+            .position(|entry| !matches!(entry, RecordEntry::Move(_)))
+            .map_or(self.entrys.len(), |i| i + 1);
+
+        Self {
+            handicap: self.handicap,
+            timelimit: self.timelimit,
+            entrys: self.entrys[..len].to_vec(),
+            metas: self.metas[..len].to_vec(),
+        }
+    }
+
+    /// 棋譜全体 (手合割、時間制限、指し手列) のハッシュ値を返す。大量の棋譜の重複除去用。
+    /// canonical() を取ったものについて計算するので、終局後の余計なエントリは無視される。
+    ///
+    /// 実行環境によらず同じ値となるよう、文字列表現の FNV-1a ハッシュを用いる。
+    pub fn game_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+        const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+        self.canonical()
+            .to_string()
+            .bytes()
+            .fold(FNV_OFFSET, |h, b| {
+                (h ^ u64::from(b)).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    /// KIF 形式の文字列を返す。
+    /// 棋譜を再生し、成/不成、打、同、王手/詰みの注釈を付ける。
+    /// 付随情報の消費時間 (と各対局者の累計) とコメントも出力する。
+    ///
+    /// KIF の手合割は上手が後手となる想定だが、ここでの駒落ちは先手側が駒を落とすので、
+    /// 平手以外は手合割を "その他" とし、開始局面を SFEN でコメントに残す。
+    pub fn to_kif_annotated(&self) -> Result<String> {
+        use std::fmt::Write;
+
+        let my = self.handicap.my();
+        let mut pos = self.handicap.initial_pos();
+
+        let mut res = String::new();
+
+        let name = |side: Side| {
+            if side == my {
+                "内藤九段"
+            } else {
+                "あなた"
+            }
+        };
+        if matches!(self.handicap, Handicap::YourSente | Handicap::MySente) {
+            writeln!(res, "手合割：平手").unwrap();
+        } else {
+            writeln!(res, "手合割：その他").unwrap();
+            writeln!(res, "*開始局面: {}", pos.to_sfen()).unwrap();
+        }
+        writeln!(res, "先手：{}", name(Side::Sente)).unwrap();
+        writeln!(res, "後手：{}", name(Side::Gote)).unwrap();
+        writeln!(res, "手数----指手---------").unwrap();
+
+        // 消費時間欄。time が None なら空文字列
+        let mut time_totals = [0; 2]; // 先に指す側, 後に指す側
+        let mut time_str = |ply: usize, meta: &MoveMeta| match meta.time {
+            Some(time) => {
+                let total = &mut time_totals[(ply - 1) % 2];
+                *total += time;
+                format!(
+                    "   ({:>2}:{:02}/{:02}:{:02}:{:02})",
+                    time / 60,
+                    time % 60,
+                    *total / 3600,
+                    *total / 60 % 60,
+                    *total % 60
+                )
+            }
+            None => String::new(),
+        };
+        let write_comment = |res: &mut String, meta: &MoveMeta| {
+            if let Some(comment) = &meta.comment {
+                for line in comment.lines() {
+                    writeln!(res, "*{}", line).unwrap();
+                }
+            }
+        };
+
+        let mut dst_prev = None;
+        for (ply, (entry, meta)) in itertools::zip(1.., self.entrys.iter().zip(&self.metas)) {
+            let mv = match entry {
+                RecordEntry::Move(mv) | RecordEntry::MyWin(mv) => mv,
+                RecordEntry::YourSuicide => {
+                    writeln!(res, "{:>4} 反則負け{}", ply, time_str(ply, meta)).unwrap();
+                    write_comment(&mut res, meta);
+                    break;
+                }
+                RecordEntry::YourWin => {
+                    writeln!(res, "{:>4} 投了{}", ply, time_str(ply, meta)).unwrap();
+                    write_comment(&mut res, meta);
+                    break;
+                }
+            };
+
+            let mv_str = move_to_kif(&pos, mv, dst_prev)?;
+            pos.do_move(mv)?;
+            dst_prev = Some(mv.dst());
+
+            writeln!(res, "{:>4} {}{}", ply, mv_str, time_str(ply, meta)).unwrap();
+            write_comment(&mut res, meta);
+            if pos.is_check() {
+                if your_move::moves_legal(&mut pos).next().is_none() {
+                    writeln!(res, "*詰み").unwrap();
+                } else {
+                    writeln!(res, "*王手").unwrap();
+                }
+            }
+            if matches!(entry, RecordEntry::MyWin(_)) {
+                writeln!(res, "*わたしの勝ち").unwrap();
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+/// KIF 形式の指し手文字列を返す。pos は指し手適用前の局面。
+fn move_to_kif(pos: &Position, mv: &Move, dst_prev: Option<Sq>) -> Result<String> {
+    let side = pos.side();
+
+    let dst_str = if dst_prev == Some(mv.dst()) {
+        "同　".to_owned()
+    } else {
+        mv.dst().pretty().into_owned()
+    };
+
+    match mv {
+        Move::Nondrop(nondrop) => {
+            let src = nondrop.src;
+            let dst = nondrop.dst;
+            let pt = pos.board()[src]
+                .piece_of(side)
+                .ok_or_else(|| Error::illegal_move(mv, "src is not my piece"))?;
+            let promo_str = if nondrop.is_promotion {
+                "成"
+            } else if can_promote(side, pt, src, dst) {
+                "不成"
+            } else {
+                ""
+            };
+            Ok(format!(
+                "{}{}{}({}{})",
+                dst_str,
+                piece_to_kif(pt),
+                promo_str,
+                10 - src.x().get(),
+                src.y().get()
+            ))
+        }
+        Move::Drop(drop) => Ok(format!("{}{}打", dst_str, piece_to_kif(drop.pt))),
+    }
+}
+
+fn piece_to_kif(pt: Piece) -> &'static str {
+    match pt {
+        Piece::Pawn => "歩",
+        Piece::Lance => "香",
+        Piece::Knight => "桂",
+        Piece::Silver => "銀",
+        Piece::Bishop => "角",
+        Piece::Rook => "飛",
+        Piece::Gold => "金",
+        Piece::King => "玉",
+        Piece::ProPawn => "と",
+        Piece::ProLance => "成香",
+        Piece::ProKnight => "成桂",
+        Piece::ProSilver => "成銀",
+        Piece::Horse => "馬",
+        Piece::Dragon => "龍",
     }
 }
 
@@ -164,10 +493,115 @@ impl std::str::FromStr for Record {
                 .collect::<Result<Vec<_>>>()?
         };
 
+        // この形式は付随情報を持たない
+        let metas = vec![MoveMeta::default(); entrys.len()];
+
         Ok(Self {
             handicap,
             timelimit,
             entrys,
+            metas,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kif_meta() {
+        let mut record = Record::new(Handicap::YourSente, false);
+        record.add_with_meta(
+            RecordEntry::Move(Move::from_sfen("7g7f").unwrap()),
+            MoveMeta {
+                time: Some(65),
+                comment: Some("角道を開ける".to_owned()),
+            },
+        );
+        record.add(RecordEntry::Move(Move::from_sfen("3c3d").unwrap()));
+        record.add_with_meta(
+            RecordEntry::YourWin,
+            MoveMeta {
+                time: Some(3600),
+                comment: None,
+            },
+        );
+        assert_eq!(record.metas().len(), record.entrys().len());
+
+        let kif = record.to_kif_annotated().unwrap();
+        let lines: Vec<_> = kif.lines().skip(4).collect();
+        assert_eq!(
+            lines,
+            [
+                "   1 ７六歩(77)   ( 1:05/00:01:05)",
+                "*角道を開ける",
+                "   2 ３四歩(33)",
+                "   3 投了   (60:00/01:01:05)",
+            ]
+        );
+
+        // テキスト形式は付随情報を保存しない
+        let parsed: Record = record.to_string().parse().unwrap();
+        assert_eq!(parsed.entrys(), record.entrys());
+        assert!(parsed
+            .metas()
+            .iter()
+            .all(|meta| *meta == MoveMeta::default()));
+    }
+
+    /// 平手で角交換する短い棋譜。your 側の 3 手目で定跡 (戦型) が変わる。
+    fn record_kakugawari() -> Record {
+        let mut record = Record::new(Handicap::YourSente, false);
+        for sfen in ["7g7f", "3c3d", "8h2b+", "3a2b"] {
+            record.add(RecordEntry::Move(Move::from_sfen(sfen).unwrap()));
+        }
+        record
+    }
+
+    #[test]
+    fn test_book_deviations() {
+        assert_eq!(record_kakugawari().book_deviations(), [3]);
+
+        let mut record = Record::new(Handicap::YourSente, false);
+        for sfen in ["7g7f", "3c3d"] {
+            record.add(RecordEntry::Move(Move::from_sfen(sfen).unwrap()));
+        }
+        assert!(record.book_deviations().is_empty());
+    }
+
+    #[test]
+    fn test_with_evals() {
+        let mut record = record_kakugawari();
+        record.add(RecordEntry::YourWin);
+
+        let evals = record.with_evals();
+        assert_eq!(evals.len(), record.entrys().len());
+        for (i, (entry, root_eval)) in evals.iter().enumerate() {
+            assert_eq!(*entry, record.entrys()[i]);
+            // your 側が先手なので、奇数番目 (0 始まり) が思考ルーチンの手番
+            assert_eq!(root_eval.is_some(), i % 2 == 1);
+        }
+        assert_eq!(evals.last().unwrap().0, RecordEntry::YourWin);
+    }
+
+    #[test]
+    fn test_game_hash() {
+        let mut record = record_kakugawari();
+        record.add(RecordEntry::YourWin);
+
+        // 終局後の余計なエントリは無視される
+        let mut record_trailing = record.clone();
+        record_trailing.add(RecordEntry::Move(Move::from_sfen("2g2f").unwrap()));
+        assert_eq!(record_trailing.canonical().entrys(), record.entrys());
+        assert_eq!(record_trailing.game_hash(), record.game_hash());
+
+        // 指し手が異なればハッシュ値も異なる
+        let mut record_other = Record::new(Handicap::YourSente, false);
+        for sfen in ["2g2f", "3c3d"] {
+            record_other.add(RecordEntry::Move(Move::from_sfen(sfen).unwrap()));
+        }
+        record_other.add(RecordEntry::YourWin);
+        assert_ne!(record_other.game_hash(), record.game_hash());
+    }
+}
@@ -6,6 +6,7 @@
 //!===================================================================
 
 use std::borrow::Cow;
+use std::fmt::Write as _;
 
 use itertools::{iproduct, Itertools};
 
@@ -19,66 +20,101 @@ pub const SFEN_HISHAOCHI: &str =
 pub const SFEN_NIMAIOCHI: &str = "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/9/LNSGKGSNL b - 1";
 
 /// SFEN 文字列をパースし、棋譜 (開始局面, 指し手リスト) を返す。
+///
+/// 局面部分は sfen_to_position() と同様に寛容に解釈する。
+/// 局面と "moves" の間にある余計なトークンは無視する。
 pub fn sfen_to_kifu(sfen: impl AsRef<str>) -> Result<(Position, Vec<Move>)> {
-    let sfen = sfen.as_ref();
+    let tokens: Vec<_> = sfen.as_ref().split_ascii_whitespace().collect();
 
-    let mut it = sfen.split_ascii_whitespace();
-    let mut next = || it.next().ok_or_else(|| Error::invalid_sfen("incomplete"));
+    let (pos, n) = tokens_to_position(&tokens, false)?;
 
-    let magic = next()?;
-    let pos = match magic {
-        "startpos" => sfen_to_position(SFEN_HIRATE),
-        "sfen" => {
-            let sfen_board = next()?;
-            let sfen_side = next()?;
-            let sfen_hands = next()?;
-            let sfen_ply = next()?;
-            let sfen_pos = [magic, sfen_board, sfen_side, sfen_hands, sfen_ply].join(" ");
-            sfen_to_position(sfen_pos)
-        }
-        _ => Err(Error::invalid_sfen(format!(
-            "invalid sfen magic: {}",
-            magic
-        ))),
-    }?;
-
-    let mvs = if let Some(magic_moves) = it.next() {
-        let sfen_moves = [magic_moves, &it.join(" ")].join(" ");
-        sfen_to_moves(sfen_moves)?
-    } else {
-        Vec::new()
+    let rest = &tokens[n..];
+    let mvs = match rest.iter().position(|&token| token == "moves") {
+        Some(i) => rest[i + 1..]
+            .iter()
+            .map(sfen_to_move)
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
     };
 
     Ok((pos, mvs))
 }
 
+/// SFEN 文字列をパースし、局面を返す。
+///
+/// GUI などから貼り付けられた文字列を受け付けやすいよう、以下を許す:
+///
+///   * "sfen" キーワードの省略
+///   * 手数の省略 (1 とみなす)
+///   * 0 以下の手数 (1 とみなす)
+///   * 末尾の余計なトークン
+///
+/// 厳密に検証したい場合は sfen_to_position_strict() を用いる。
 pub fn sfen_to_position(sfen: impl AsRef<str>) -> Result<Position> {
-    let sfen = sfen.as_ref();
+    let tokens: Vec<_> = sfen.as_ref().split_ascii_whitespace().collect();
 
-    let mut it = sfen.split_ascii_whitespace();
-    let mut next = || it.next().ok_or_else(|| Error::invalid_sfen("incomplete"));
+    let (pos, _) = tokens_to_position(&tokens, false)?;
 
-    let magic = next()?;
-    match magic {
-        "startpos" => Ok(sfen_to_position(SFEN_HIRATE)?),
-        "sfen" => {
-            let sfen_board = next()?;
-            let sfen_side = next()?;
-            let sfen_hands = next()?;
-            let sfen_ply = next()?;
-
-            let board = sfen_to_board(sfen_board)?;
-            let side = sfen_to_side(sfen_side)?;
-            let hands = sfen_to_hands(sfen_hands)?;
-            let ply = sfen_to_ply(sfen_ply)?;
-
-            Ok(Position::new(side, board, hands, ply))
+    Ok(pos)
+}
+
+/// SFEN 文字列を厳密にパースし、局面を返す。
+/// "startpos" または "sfen <盤面> <手番> <持駒> <手数>" のみを受け付ける。手数は 1 以上。
+pub fn sfen_to_position_strict(sfen: impl AsRef<str>) -> Result<Position> {
+    let tokens: Vec<_> = sfen.as_ref().split_ascii_whitespace().collect();
+
+    let (pos, n) = tokens_to_position(&tokens, true)?;
+    chk!(
+        n == tokens.len(),
+        Error::invalid_sfen("extra tokens after position")
+    );
+
+    Ok(pos)
+}
+
+/// トークン列の先頭から局面をパースし、(局面, 消費したトークン数) を返す。
+fn tokens_to_position(tokens: &[&str], strict: bool) -> Result<(Position, usize)> {
+    let token = |i: usize| {
+        tokens
+            .get(i)
+            .copied()
+            .ok_or_else(|| Error::invalid_sfen("incomplete"))
+    };
+
+    let magic = token(0)?;
+    let mut i = match magic {
+        "startpos" => return Ok((sfen_to_position_strict(SFEN_HIRATE)?, 1)),
+        "sfen" => 1,
+        _ if !strict && magic.contains('/') => 0,
+        _ => {
+            return Err(Error::invalid_sfen(format!(
+                "invalid sfen magic: {}",
+                magic
+            )))
         }
-        _ => Err(Error::invalid_sfen(format!(
-            "invalid sfen magic: {}",
-            magic
-        ))),
-    }
+    };
+
+    let board = sfen_to_board(token(i)?)?;
+    let side = sfen_to_side(token(i + 1)?)?;
+    let hands = sfen_to_hands(token(i + 2)?)?;
+    i += 3;
+
+    let ply = if strict {
+        let ply = sfen_to_ply(token(i)?)?;
+        chk!(ply >= 1, Error::invalid_sfen("ply: must be positive"));
+        i += 1;
+        ply
+    } else {
+        match tokens.get(i).and_then(|s| s.parse::<i32>().ok()) {
+            Some(ply) => {
+                i += 1;
+                std::cmp::max(ply, 1)
+            }
+            None => 1,
+        }
+    };
+
+    Ok((Position::new(side, board, hands, ply), i))
 }
 
 pub fn sfen_to_board(sfen: impl AsRef<str>) -> Result<Board> {
@@ -127,9 +163,10 @@ fn sfen_to_board_row(sfen: impl AsRef<str>) -> Result<Vec<BoardCell>> {
                     self.ensure_size_ok(n)?;
                     self.row.extend(itertools::repeat_n(BoardCell::Empty, n));
                 }
-                c if is_piece_char(c) => {
+                c => {
+                    let mut pt = Piece::from_sfen_char(c)
+                        .ok_or_else(|| Error::invalid_sfen("board_row: invalid char"))?;
                     self.ensure_size_ok(1)?;
-                    let mut pt = char_to_piece(c);
                     if self.is_promote {
                         pt = pt
                             .to_promoted()
@@ -145,7 +182,6 @@ fn sfen_to_board_row(sfen: impl AsRef<str>) -> Result<Vec<BoardCell>> {
                     };
                     self.row.push(cell);
                 }
-                _ => return Err(Error::invalid_sfen("board_row: invalid char")),
             }
             Ok(())
         }
@@ -177,6 +213,55 @@ fn sfen_to_board_row(sfen: impl AsRef<str>) -> Result<Vec<BoardCell>> {
     Ok(state.row)
 }
 
+/// 9x9 の文字配列から盤面を得る。テスト局面の記述用。
+/// rows[0] が一段目、rows[y][0] が 9 筋となる(SFEN と同じ並び)。
+///
+/// 大文字が先手、小文字が後手、'.' が空白。駒文字は SFEN と同じ。
+/// 成駒は 1 文字で表すため、以下の文字を用いる:
+///
+/// * 'T': と
+/// * 'Y': 成香
+/// * 'E': 成桂
+/// * 'I': 成銀
+/// * 'H': 馬
+/// * 'D': 龍
+pub fn grid_to_board(rows: &[[char; 9]; 9]) -> Result<Board> {
+    let mut board = Board::empty();
+    for (y, row) in itertools::zip(1.., rows) {
+        for (cell, &c) in itertools::zip(board.row_valid_mut(y), row) {
+            *cell = grid_char_to_cell(c)?;
+        }
+    }
+
+    Ok(board)
+}
+
+fn grid_char_to_cell(c: char) -> Result<BoardCell> {
+    if c == '.' {
+        return Ok(BoardCell::Empty);
+    }
+
+    let pt = if let Some(pt) = Piece::from_sfen_char(c) {
+        pt
+    } else {
+        match c.to_ascii_uppercase() {
+            'T' => Piece::ProPawn,
+            'Y' => Piece::ProLance,
+            'E' => Piece::ProKnight,
+            'I' => Piece::ProSilver,
+            'H' => Piece::Horse,
+            'D' => Piece::Dragon,
+            _ => return Err(Error::invalid_sfen(format!("grid: invalid char: {:?}", c))),
+        }
+    };
+
+    if c.is_ascii_uppercase() {
+        Ok(BoardCell::Sente(pt))
+    } else {
+        Ok(BoardCell::Gote(pt))
+    }
+}
+
 pub fn sfen_to_side(sfen: impl AsRef<str>) -> Result<Side> {
     let sfen = sfen.as_ref();
 
@@ -212,8 +297,9 @@ pub fn sfen_to_hands(sfen: impl AsRef<str>) -> Result<Hands> {
                     self.count *= 10;
                     self.count += c.to_digit(10).unwrap() as u8;
                 }
-                c if is_piece_char(c) => {
-                    let pt = char_to_piece(c);
+                c => {
+                    let pt = Piece::from_sfen_char(c)
+                        .ok_or_else(|| Error::invalid_sfen("hands: invalid char"))?;
                     chk!(pt.is_hand(), Error::invalid_sfen("hands: not hand piece"));
                     if self.count == 0 {
                         self.count = 1;
@@ -228,7 +314,6 @@ pub fn sfen_to_hands(sfen: impl AsRef<str>) -> Result<Hands> {
                     self.hands[side][pt] += self.count;
                     self.count = 0;
                 }
-                _ => return Err(Error::invalid_sfen("hands: invalid char")),
             }
             Ok(())
         }
@@ -280,11 +365,9 @@ pub fn sfen_to_move(sfen: impl AsRef<str>) -> Result<Move> {
     }
 
     if cs[1] == '*' {
-        chk!(
-            cs[0].is_ascii_uppercase() && is_piece_char(cs[0]),
-            Error::invalid_sfen(format!("invalid piece: {}", sfen))
-        );
-        let pt = char_to_piece(cs[0]);
+        let pt = Piece::from_sfen_char(cs[0])
+            .filter(|_| cs[0].is_ascii_uppercase())
+            .ok_or_else(|| Error::invalid_sfen(format!("invalid piece: {}", sfen)))?;
         let dst = chars_to_sq(cs[2], cs[3])?;
         Ok(Move::drop(pt, dst))
     } else {
@@ -295,39 +378,12 @@ pub fn sfen_to_move(sfen: impl AsRef<str>) -> Result<Move> {
     }
 }
 
-fn is_piece_char(c: char) -> bool {
-    matches!(
-        c.to_ascii_uppercase(),
-        'P' | 'L' | 'N' | 'S' | 'B' | 'R' | 'G' | 'K'
-    )
-}
-
-fn char_to_piece(c: char) -> Piece {
-    match c.to_ascii_uppercase() {
-        'P' => Piece::Pawn,
-        'L' => Piece::Lance,
-        'N' => Piece::Knight,
-        'S' => Piece::Silver,
-        'B' => Piece::Bishop,
-        'R' => Piece::Rook,
-        'G' => Piece::Gold,
-        'K' => Piece::King,
-        _ => unreachable!(),
-    }
-}
-
-fn chars_to_sq(cx: char, cy: char) -> Result<Sq> {
-    chk!(
-        ('1'..='9').contains(&cx),
-        Error::invalid_sfen(format!("invalid x: {}", cx))
-    );
-    chk!(
-        ('a'..='i').contains(&cy),
-        Error::invalid_sfen(format!("invalid y: {:?}", cy))
-    );
-    let x = 10 - (cx as u8 - b'0');
-    let y = cy as u8 - b'a' + 1;
-    Ok(Sq::from_xy(x.into(), y.into()))
+pub(crate) fn chars_to_sq(cx: char, cy: char) -> Result<Sq> {
+    let x =
+        SqX::from_usi_char(cx).ok_or_else(|| Error::invalid_sfen(format!("invalid x: {}", cx)))?;
+    let y = SqY::from_usi_char(cy)
+        .ok_or_else(|| Error::invalid_sfen(format!("invalid y: {:?}", cy)))?;
+    Ok(Sq::from_xy(x.get(), y.get()))
 }
 
 pub fn kifu_to_sfen(pos: &Position, mvs: &[Move]) -> Cow<'static, str> {
@@ -341,73 +397,72 @@ pub fn kifu_to_sfen(pos: &Position, mvs: &[Move]) -> Cow<'static, str> {
 }
 
 pub fn position_to_sfen(pos: &Position) -> Cow<'static, str> {
-    let sfen_board = board_to_sfen(pos.board());
-    let sfen_side = side_to_sfen(pos.side());
-    let sfen_hands = hands_to_sfen(pos.hands());
-    let sfen_ply = ply_to_sfen(pos.ply());
-
-    ["sfen".into(), sfen_board, sfen_side, sfen_hands, sfen_ply]
-        .join(" ")
-        .into()
+    let mut buf = String::new();
+    position_to_sfen_into(pos, &mut buf);
+    buf.into()
+}
+
+/// position_to_sfen() の結果を buf の末尾に追記する。大量の局面を出力する際の再アロケート回避用。
+pub fn position_to_sfen_into(pos: &Position, buf: &mut String) {
+    buf.push_str("sfen ");
+    board_to_sfen_into(pos.board(), buf);
+    buf.push(' ');
+    buf.push_str(&side_to_sfen(pos.side()));
+    buf.push(' ');
+    hands_to_sfen_into(pos.hands(), buf);
+    buf.push(' ');
+    write!(buf, "{}", pos.ply()).unwrap();
 }
 
 pub fn board_to_sfen(board: &Board) -> Cow<'static, str> {
-    (1..=9)
-        .map(|y| board_row_to_sfen(board.row_valid(y)))
-        .join("/")
-        .into()
+    let mut buf = String::new();
+    board_to_sfen_into(board, &mut buf);
+    buf.into()
 }
 
-fn board_row_to_sfen(row: &[BoardCell]) -> Cow<'static, str> {
-    struct State {
-        sfen: String,
-        n_empty: i32,
-        n_processed: i32,
+/// board_to_sfen() の結果を buf の末尾に追記する。
+pub fn board_to_sfen_into(board: &Board, buf: &mut String) {
+    for y in 1..=9 {
+        if y > 1 {
+            buf.push('/');
+        }
+        board_row_to_sfen_into(board.row_valid(y), buf);
     }
-    impl State {
-        fn new() -> Self {
-            Self {
-                sfen: String::new(),
-                n_empty: 0,
-                n_processed: 0,
-            }
+}
+
+fn board_row_to_sfen_into(row: &[BoardCell], buf: &mut String) {
+    fn flush_emptys(buf: &mut String, n_empty: &mut u8) {
+        if *n_empty > 0 {
+            buf.push(char::from(b'0' + *n_empty));
+            *n_empty = 0;
         }
-        fn eat(&mut self, cell: &BoardCell) {
-            match cell {
-                BoardCell::Empty => {
-                    self.n_empty += 1;
-                    self.n_processed += 1;
-                    if self.n_processed == 9 {
-                        self.flush_emptys();
-                    }
-                }
-                BoardCell::Sente(pt) => {
-                    self.flush_emptys();
-                    self.sfen.push_str(&piece_to_sfen(*pt));
-                    self.n_processed += 1;
-                }
-                BoardCell::Gote(pt) => {
-                    self.flush_emptys();
-                    self.sfen.push_str(&piece_to_sfen(*pt).to_ascii_lowercase());
-                    self.n_processed += 1;
-                }
-                BoardCell::Wall => unreachable!(),
+    }
+
+    let mut n_empty = 0;
+    for cell in row {
+        match cell {
+            BoardCell::Empty => n_empty += 1,
+            BoardCell::Sente(pt) => {
+                flush_emptys(buf, &mut n_empty);
+                push_piece_sfen(buf, Side::Sente, *pt);
             }
-        }
-        fn flush_emptys(&mut self) {
-            if self.n_empty > 0 {
-                self.sfen.push_str(&self.n_empty.to_string());
-                self.n_empty = 0;
+            BoardCell::Gote(pt) => {
+                flush_emptys(buf, &mut n_empty);
+                push_piece_sfen(buf, Side::Gote, *pt);
             }
+            BoardCell::Wall => unreachable!(),
         }
     }
+    flush_emptys(buf, &mut n_empty);
+}
 
-    let mut state = State::new();
-    for cell in row {
-        state.eat(cell);
+/// 先手の駒は大文字、後手の駒は小文字で buf に追記する。
+fn push_piece_sfen(buf: &mut String, side: Side, pt: Piece) {
+    let s = pt.to_sfen_char();
+    match side {
+        Side::Sente => buf.push_str(s),
+        Side::Gote => buf.extend(s.chars().map(|c| c.to_ascii_lowercase())),
     }
-
-    state.sfen.into()
 }
 
 pub fn side_to_sfen(side: Side) -> Cow<'static, str> {
@@ -421,6 +476,13 @@ pub fn side_to_sfen(side: Side) -> Cow<'static, str> {
 /// これが一般的らしい
 /// (https://ch.nicovideo.jp/kifuwarabe/blomaga/ar795371)
 pub fn hands_to_sfen(hands: &Hands) -> Cow<'static, str> {
+    let mut buf = String::new();
+    hands_to_sfen_into(hands, &mut buf);
+    buf.into()
+}
+
+/// hands_to_sfen() の結果を buf の末尾に追記する。
+pub fn hands_to_sfen_into(hands: &Hands, buf: &mut String) {
     const PIECES: &[Piece] = &[
         Piece::Rook,
         Piece::Bishop,
@@ -432,25 +494,20 @@ pub fn hands_to_sfen(hands: &Hands) -> Cow<'static, str> {
     ];
 
     if hands.is_empty() {
-        return "-".into();
+        buf.push('-');
+        return;
     }
 
-    let mut sfen = String::new();
     for (side, pt) in iproduct!(&[Side::Sente, Side::Gote], PIECES) {
         let n = hands[*side][*pt];
         if n == 0 {
             continue;
         }
         if n >= 2 {
-            sfen.push_str(&n.to_string());
-        }
-        match side {
-            Side::Sente => sfen.push_str(&piece_to_sfen(*pt)),
-            Side::Gote => sfen.push_str(&piece_to_sfen(*pt).to_ascii_lowercase()),
+            write!(buf, "{}", n).unwrap();
         }
+        push_piece_sfen(buf, *side, *pt);
     }
-
-    sfen.into()
 }
 
 pub fn ply_to_sfen(ply: i32) -> Cow<'static, str> {
@@ -470,40 +527,15 @@ pub fn move_to_sfen(mv: &Move) -> Cow<'static, str> {
     match mv {
         Move::Nondrop(nondrop) => format!(
             "{}{}{}",
-            sq_to_sfen(nondrop.src),
-            sq_to_sfen(nondrop.dst),
+            nondrop.src.to_usi(),
+            nondrop.dst.to_usi(),
             if nondrop.is_promotion { "+" } else { "" }
         )
         .into(),
-        Move::Drop(drop) => format!("{}*{}", piece_to_sfen(drop.pt), sq_to_sfen(drop.dst)).into(),
-    }
-}
-
-fn piece_to_sfen(pt: Piece) -> Cow<'static, str> {
-    match pt {
-        Piece::Pawn => "P".into(),
-        Piece::Lance => "L".into(),
-        Piece::Knight => "N".into(),
-        Piece::Silver => "S".into(),
-        Piece::Bishop => "B".into(),
-        Piece::Rook => "R".into(),
-        Piece::Gold => "G".into(),
-        Piece::King => "K".into(),
-        Piece::ProPawn => "+P".into(),
-        Piece::ProLance => "+L".into(),
-        Piece::ProKnight => "+N".into(),
-        Piece::ProSilver => "+S".into(),
-        Piece::Horse => "+B".into(),
-        Piece::Dragon => "+R".into(),
+        Move::Drop(drop) => format!("{}*{}", drop.pt.to_sfen_char(), drop.dst.to_usi()).into(),
     }
 }
 
-fn sq_to_sfen(sq: Sq) -> Cow<'static, str> {
-    let cx = char::from(10 - sq.x().get() as u8 + b'0');
-    let cy = char::from(sq.y().get() as u8 - 1 + b'a');
-    format!("{}{}", cx, cy).into()
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -537,4 +569,138 @@ mod tests {
         );
         chk2("startpos moves 7g7f 3c3d 8h2b+ 3a2b B*4e B*8e 4e3d 8e7f", "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1 moves 7g7f 3c3d 8h2b+ 3a2b B*4e B*8e 4e3d 8e7f");
     }
+
+    #[test]
+    fn test_piece_sfen_char() {
+        // 大文字小文字を問わずナマ駒を返す
+        for pt in Piece::iter_hand().chain(std::iter::once(Piece::King)) {
+            let c = pt.to_sfen_char().chars().next().unwrap();
+            assert_eq!(Piece::from_sfen_char(c), Some(pt));
+            assert_eq!(Piece::from_sfen_char(c.to_ascii_lowercase()), Some(pt));
+        }
+        assert_eq!(Piece::from_sfen_char('+'), None);
+        assert_eq!(Piece::from_sfen_char('x'), None);
+
+        assert_eq!(Piece::Dragon.to_sfen_char(), "+R");
+        assert_eq!(Piece::ProPawn.to_sfen_char(), "+P");
+
+        // 金、玉は成れない
+        assert_eq!(Piece::from_sfen_char('K').unwrap().to_promoted(), None);
+        assert_eq!(Piece::from_sfen_char('G').unwrap().to_promoted(), None);
+        assert_eq!(
+            Piece::from_sfen_char('b').unwrap().to_promoted(),
+            Some(Piece::Horse)
+        );
+    }
+
+    #[test]
+    fn test_sq_usi() {
+        for sq in Sq::iter_valid() {
+            let usi = sq.to_usi();
+            assert_eq!(Sq::from_usi(&usi).unwrap(), sq);
+            // 指し手の SFEN 表記もこれに委譲している
+            let mv = Move::drop(Piece::Gold, sq);
+            assert_eq!(move_to_sfen(&mv), format!("G*{}", usi));
+            assert_eq!(sfen_to_move(format!("G*{}", usi)).unwrap(), mv);
+        }
+
+        for s in ["", "7", "0f", "7j", "7F", "f7", "7f7", "７f"] {
+            assert!(Sq::from_usi(s).is_err(), "{:?}", s);
+        }
+        assert_eq!(SqX::from_usi_char('0'), None);
+        assert_eq!(SqY::from_usi_char('j'), None);
+    }
+
+    #[test]
+    fn test_move_display() {
+        for sfen in ["7g7f", "8h2b+", "2b3c", "P*5e", "R*1a"] {
+            let mv = Move::from_sfen(sfen).unwrap();
+            assert_eq!(mv.to_sfen(), sfen);
+            assert_eq!(mv.to_string(), sfen);
+            assert_eq!(Move::from_sfen(mv.to_string()).unwrap(), mv);
+            match &mv {
+                Move::Nondrop(nondrop) => assert_eq!(nondrop.to_string(), sfen),
+                Move::Drop(drop) => assert_eq!(drop.to_string(), sfen),
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_sfen_into() {
+        let sfen =
+            "sfen 8l/1l+R2P3/p2pBG1pp/kps1p4/Nn1P2G2/P1P1P2PP/1PS6/1KSG3+r1/LN2+p3L w Sbgn3p 1";
+        let pos = sfen_to_position(sfen).unwrap();
+
+        // バッファは使い回され、末尾に追記される
+        let mut buf = String::new();
+        for _ in 0..2 {
+            buf.clear();
+            position_to_sfen_into(&pos, &mut buf);
+            assert_eq!(buf, sfen);
+        }
+
+        let mut buf = String::from("board: ");
+        pos.board().to_sfen_into(&mut buf);
+        buf.push_str(", hands: ");
+        pos.hands().to_sfen_into(&mut buf);
+        assert_eq!(
+            buf,
+            "board: 8l/1l+R2P3/p2pBG1pp/kps1p4/Nn1P2G2/P1P1P2PP/1PS6/1KSG3+r1/LN2+p3L, hands: Sbgn3p"
+        );
+    }
+
+    #[test]
+    fn test_tolerant() {
+        let pos_hirate = sfen_to_position(SFEN_HIRATE).unwrap();
+
+        for sfen in &[
+            "  startpos  ",
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b -",
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 0",
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1 foo",
+        ] {
+            assert_eq!(sfen_to_position(sfen).unwrap(), pos_hirate);
+        }
+
+        chk2(
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - moves 7g7f",
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1 moves 7g7f",
+        );
+
+        assert!(sfen_to_position_strict(SFEN_HIRATE).is_ok());
+        assert!(sfen_to_position_strict("startpos").is_ok());
+        for sfen in &[
+            "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1",
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b -",
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 0",
+            "sfen lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1 foo",
+        ] {
+            assert!(sfen_to_position_strict(sfen).is_err());
+        }
+    }
+
+    #[test]
+    fn test_grid() {
+        let rows = [
+            ['.', '.', '.', '.', '.', '.', '.', '.', 'l'],
+            ['.', 'l', 'D', '.', '.', 'P', '.', '.', '.'],
+            ['p', '.', '.', 'p', 'B', 'G', '.', 'p', 'p'],
+            ['k', 'p', 's', '.', 'p', '.', '.', '.', '.'],
+            ['N', 'n', '.', 'P', '.', '.', 'G', '.', '.'],
+            ['P', '.', 'P', '.', 'P', '.', '.', 'P', 'P'],
+            ['.', 'P', 'S', '.', '.', '.', '.', '.', '.'],
+            ['.', 'K', 'S', 'G', '.', '.', '.', 'd', '.'],
+            ['L', 'N', '.', '.', 't', '.', '.', '.', 'L'],
+        ];
+        assert_eq!(
+            grid_to_board(&rows).unwrap(),
+            sfen_to_board("8l/1l+R2P3/p2pBG1pp/kps1p4/Nn1P2G2/P1P1P2PP/1PS6/1KSG3+r1/LN2+p3L")
+                .unwrap()
+        );
+
+        let mut rows_invalid = rows;
+        rows_invalid[4][4] = 'x';
+        assert!(grid_to_board(&rows_invalid).is_err());
+    }
 }
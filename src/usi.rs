@@ -1,5 +1,7 @@
-use crate::ai::Ai;
-use crate::log::NullLogger;
+use crate::ai::{Ai, BestEval, CandEval, PositionEval, RootEval};
+use crate::book::{BookState, Formation};
+use crate::effect::EffectBoard;
+use crate::log::{LoggerTrait, NullLogger};
 use crate::prelude::*;
 use crate::record::RecordEntry;
 use crate::sfen;
@@ -20,6 +22,32 @@ impl<'a> Cmd<'a> {
     }
 }
 
+/// 注釈を info string として出力するロガー。
+#[derive(Debug)]
+struct InfoLogger;
+
+impl LoggerTrait for InfoLogger {
+    fn log_progress(&mut self, _ply: u8, _level: u8, _level_sub: u8) {}
+    fn log_book_state(&mut self, _book_state: BookState) {}
+
+    fn log_root_eval(&mut self, _root_eval: RootEval) {}
+    fn log_root_eff_board(&mut self, _eff_board: EffectBoard) {}
+
+    fn start_cand(&mut self, _mv: Move) {}
+    fn log_cand_eff_board(&mut self, _eff_board: EffectBoard) {}
+    fn log_cand_pos_eval(&mut self, _pos_eval: PositionEval) {}
+    fn log_cand_eval(&mut self, _cand_eval: CandEval) {}
+    fn log_cand_improve(&mut self) {}
+    fn end_cand(&mut self) {}
+
+    fn log_best_eval(&mut self, _best_eval: BestEval) {}
+    fn log_record_entry(&mut self, _record_entry: RecordEntry) {}
+
+    fn log_comment(&mut self, comment: String) {
+        println!("info string {}", comment);
+    }
+}
+
 fn get_handicap(pos: &Position, my: Side) -> Option<Handicap> {
     let pos_hirate = Position::from_sfen(sfen::SFEN_HIRATE).unwrap();
     let pos_hishaochi = Position::from_sfen(sfen::SFEN_HISHAOCHI).unwrap();
@@ -64,43 +92,8 @@ fn parse_position_cmd(args: &[&str], timelimit: bool) -> Result<Ai> {
     // mvs を再生し、現局面まで進める
     // AI 側の手は一致するものと仮定する
     for mv in mvs {
-        if ai.pos().side() == my {
-            let mut logger = NullLogger::new();
-            match ai.think(&mut logger) {
-                RecordEntry::Move(mv_actual) => {
-                    if mv != mv_actual {
-                        return Err(Error::invalid_usi_cmd(format!(
-                            "move mismatch (sfen: {:?}, actual: {:?}",
-                            mv, mv_actual
-                        )));
-                    }
-                    ai.move_my(&mv);
-                }
-                RecordEntry::MyWin(mv_actual) => {
-                    if mv != mv_actual {
-                        return Err(Error::invalid_usi_cmd(format!(
-                            "move mismatch (sfen: {:?}, actual: {:?}",
-                            mv, mv_actual
-                        )));
-                    }
-                    ai.move_my(&mv);
-                }
-                RecordEntry::YourSuicide => {
-                    return Err(Error::invalid_usi_cmd(format!(
-                        "move mismatch (sfen: {:?}, actual: YourSuicide",
-                        mv
-                    )));
-                }
-                RecordEntry::YourWin => {
-                    return Err(Error::invalid_usi_cmd(format!(
-                        "move mismatch (sfen: {:?}, actual: YourWin",
-                        mv
-                    )));
-                }
-            }
-        } else {
-            ai.move_your(&mv);
-        }
+        ai.replay_move(&mv)
+            .map_err(|e| Error::invalid_usi_cmd(e.to_string()))?;
     }
 
     Ok(ai)
@@ -126,6 +119,7 @@ impl StateInitial {
         println!("id name {}", ENGINE_NAME);
         println!("id author {}", ENGINE_AUTHOR);
         println!("option name timelimit type check default false");
+        println!("option name verbose type check default false");
         println!("usiok");
 
         Ok(State::NotReady(StateNotReady::new()))
@@ -135,11 +129,15 @@ impl StateInitial {
 #[derive(Debug, Eq, PartialEq)]
 struct StateNotReady {
     timelimit: bool,
+    verbose: bool,
 }
 
 impl StateNotReady {
     fn new() -> Self {
-        Self { timelimit: false }
+        Self {
+            timelimit: false,
+            verbose: false,
+        }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -154,10 +152,10 @@ impl StateNotReady {
     fn on_cmd_isready(self) -> Result<State> {
         println!("readyok");
 
-        Ok(State::Ready(StateReady::new(self.timelimit)))
+        Ok(State::Ready(StateReady::new(self.timelimit, self.verbose)))
     }
 
-    /// name timelimit value <true|false> のみ対応。
+    /// name <timelimit|verbose> value <true|false> のみ対応。
     fn on_cmd_setoption(mut self, args: &[&str]) -> Result<State> {
         if args.len() != 4 {
             return Ok(State::NotReady(self));
@@ -175,11 +173,13 @@ impl StateNotReady {
         let name = args[1];
         let value = args[3];
 
-        if name != "timelimit" {
-            return Ok(State::NotReady(self));
-        }
+        let opt = match name {
+            "timelimit" => &mut self.timelimit,
+            "verbose" => &mut self.verbose,
+            _ => return Ok(State::NotReady(self)),
+        };
 
-        self.timelimit = value
+        *opt = value
             .parse()
             .map_err(|e| Error::invalid_usi_cmd(format!("bool parse error: {}", e)))?;
 
@@ -190,11 +190,12 @@ impl StateNotReady {
 #[derive(Debug, Eq, PartialEq)]
 struct StateReady {
     timelimit: bool,
+    verbose: bool,
 }
 
 impl StateReady {
-    fn new(timelimit: bool) -> Self {
-        Self { timelimit }
+    fn new(timelimit: bool, verbose: bool) -> Self {
+        Self { timelimit, verbose }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -208,6 +209,7 @@ impl StateReady {
     fn on_cmd_usinewgame(self) -> Result<State> {
         Ok(State::WaitingPosition(StateWaitingPosition::new(
             self.timelimit,
+            self.verbose,
         )))
     }
 }
@@ -215,11 +217,12 @@ impl StateReady {
 #[derive(Debug, Eq, PartialEq)]
 struct StateWaitingPosition {
     timelimit: bool,
+    verbose: bool,
 }
 
 impl StateWaitingPosition {
-    fn new(timelimit: bool) -> Self {
-        Self { timelimit }
+    fn new(timelimit: bool, verbose: bool) -> Self {
+        Self { timelimit, verbose }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -233,7 +236,11 @@ impl StateWaitingPosition {
 
     fn on_cmd_position(self, args: &[&str]) -> Result<State> {
         let ai = parse_position_cmd(args, self.timelimit)?;
-        Ok(State::Playing(StatePlaying::new(self.timelimit, ai)))
+        Ok(State::Playing(StatePlaying::new(
+            self.timelimit,
+            self.verbose,
+            ai,
+        )))
     }
 
     fn on_cmd_gameover(self) -> Result<State> {
@@ -246,14 +253,18 @@ impl StateWaitingPosition {
 #[derive(Debug, Eq, PartialEq)]
 struct StatePlaying {
     timelimit: bool,
-    ai: Box<Ai>, // State のコピーコストを抑えるため Box に
+    verbose: bool,
+    ai: Box<Ai>,                  // State のコピーコストを抑えるため Box に
+    formation: Option<Formation>, // 最後に info string で報告した戦型
 }
 
 impl StatePlaying {
-    fn new(timelimit: bool, ai: Ai) -> Self {
+    fn new(timelimit: bool, verbose: bool, ai: Ai) -> Self {
         Self {
             timelimit,
+            verbose,
             ai: Box::new(ai),
+            formation: None,
         }
     }
 
@@ -269,13 +280,20 @@ impl StatePlaying {
     }
 
     fn on_cmd_go(mut self, _args: &[&str]) -> Result<State> {
-        let mut logger = NullLogger::new();
-        let mv_str = match self.ai.think(&mut logger) {
+        let entry = if self.verbose {
+            self.ai.think(&mut InfoLogger)
+        } else {
+            self.ai.think(&mut NullLogger::new())
+        };
+        let mv_str = match entry {
             RecordEntry::Move(mv) => Ok(sfen::move_to_sfen(&mv)),
             RecordEntry::MyWin(mv) => Ok(sfen::move_to_sfen(&mv)),
             RecordEntry::YourSuicide => Err(Error::invalid_usi_cmd("YourSuicide")),
             RecordEntry::YourWin => Ok("resign".into()),
         }?;
+        if self.verbose {
+            self.report_formation();
+        }
         println!("bestmove {}", mv_str);
 
         Ok(State::Playing(self))
@@ -286,6 +304,18 @@ impl StatePlaying {
         Ok(State::Playing(self))
     }
 
+    /// 戦型が定まった、または変わったとき、それを info string として出力する。
+    fn report_formation(&mut self) {
+        let formation = self.ai.book_state().formation();
+        if self.formation == Some(formation) {
+            return;
+        }
+        if self.formation.is_some() || formation != Formation::Nothing {
+            println!("info string formation: {}", formation);
+        }
+        self.formation = Some(formation);
+    }
+
     fn on_cmd_stop(self) -> Result<State> {
         // FIXME: go infinite 未対応なので、とりあえず単に無視
         Ok(State::Playing(self))
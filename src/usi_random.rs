@@ -1,6 +1,6 @@
 use crate::prelude::*;
 use crate::sfen;
-use crate::your_move;
+use crate::your_move::{self, IllegalPolicy};
 use crate::{Error, Result};
 
 const ENGINE_NAME: &str = "naitou_clone_random";
@@ -30,11 +30,13 @@ fn parse_position_cmd(args: &[&str]) -> Result<Position> {
 }
 
 #[derive(Debug, Eq, PartialEq)]
-struct StateInitial;
+struct StateInitial {
+    policy: IllegalPolicy,
+}
 
 impl StateInitial {
-    fn new() -> Self {
-        Self
+    fn new(policy: IllegalPolicy) -> Self {
+        Self { policy }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -50,16 +52,18 @@ impl StateInitial {
         println!("id author {}", ENGINE_AUTHOR);
         println!("usiok");
 
-        Ok(State::NotReady(StateNotReady::new()))
+        Ok(State::NotReady(StateNotReady::new(self.policy)))
     }
 }
 
 #[derive(Debug, Eq, PartialEq)]
-struct StateNotReady;
+struct StateNotReady {
+    policy: IllegalPolicy,
+}
 
 impl StateNotReady {
-    fn new() -> Self {
-        Self
+    fn new(policy: IllegalPolicy) -> Self {
+        Self { policy }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -74,7 +78,7 @@ impl StateNotReady {
     fn on_cmd_isready(self) -> Result<State> {
         println!("readyok");
 
-        Ok(State::Ready(StateReady::new()))
+        Ok(State::Ready(StateReady::new(self.policy)))
     }
 
     fn on_cmd_setoption(self) -> Result<State> {
@@ -84,11 +88,13 @@ impl StateNotReady {
 }
 
 #[derive(Debug, Eq, PartialEq)]
-struct StateReady;
+struct StateReady {
+    policy: IllegalPolicy,
+}
 
 impl StateReady {
-    fn new() -> Self {
-        Self
+    fn new(policy: IllegalPolicy) -> Self {
+        Self { policy }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -100,16 +106,20 @@ impl StateReady {
     }
 
     fn on_cmd_usinewgame(self) -> Result<State> {
-        Ok(State::WaitingPosition(StateWaitingPosition::new()))
+        Ok(State::WaitingPosition(StateWaitingPosition::new(
+            self.policy,
+        )))
     }
 }
 
 #[derive(Debug, Eq, PartialEq)]
-struct StateWaitingPosition;
+struct StateWaitingPosition {
+    policy: IllegalPolicy,
+}
 
 impl StateWaitingPosition {
-    fn new() -> Self {
-        Self
+    fn new(policy: IllegalPolicy) -> Self {
+        Self { policy }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -123,11 +133,11 @@ impl StateWaitingPosition {
 
     fn on_cmd_position(self, args: &[&str]) -> Result<State> {
         let pos = parse_position_cmd(args)?;
-        Ok(State::Playing(StatePlaying::new(pos)))
+        Ok(State::Playing(StatePlaying::new(pos, self.policy)))
     }
 
     fn on_cmd_gameover(self) -> Result<State> {
-        Ok(State::NotReady(StateNotReady::new()))
+        Ok(State::NotReady(StateNotReady::new(self.policy)))
     }
 }
 
@@ -136,11 +146,15 @@ impl StateWaitingPosition {
 #[derive(Debug, Eq, PartialEq)]
 struct StatePlaying {
     pos: Box<Position>, // State のコピーコストを抑えるため Box に
+    policy: IllegalPolicy,
 }
 
 impl StatePlaying {
-    fn new(pos: Position) -> Self {
-        Self { pos: Box::new(pos) }
+    fn new(pos: Position, policy: IllegalPolicy) -> Self {
+        Self {
+            pos: Box::new(pos),
+            policy,
+        }
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<State> {
@@ -155,10 +169,11 @@ impl StatePlaying {
     }
 
     fn on_cmd_go(mut self, _args: &[&str]) -> Result<State> {
-        use rand::seq::IteratorRandom;
+        use rand::seq::SliceRandom;
         let mut rng = rand::thread_rng();
-        let mv_str = match your_move::moves_legal(&mut self.pos).choose(&mut rng) {
-            Some(mv) => sfen::move_to_sfen(&mv),
+        let mvs = your_move::moves_with_policy(&mut self.pos, self.policy);
+        let mv_str = match mvs.choose(&mut rng) {
+            Some(mv) => sfen::move_to_sfen(mv),
             None => "resign".into(),
         };
         println!("bestmove {}", mv_str);
@@ -177,7 +192,7 @@ impl StatePlaying {
     }
 
     fn on_cmd_gameover(self) -> Result<State> {
-        Ok(State::NotReady(StateNotReady::new()))
+        Ok(State::NotReady(StateNotReady::new(self.policy)))
     }
 }
 
@@ -192,8 +207,8 @@ enum State {
 }
 
 impl State {
-    fn new() -> Self {
-        Self::Initial(StateInitial::new())
+    fn new(policy: IllegalPolicy) -> Self {
+        Self::Initial(StateInitial::new(policy))
     }
 
     fn on_cmd(self, cmd: &Cmd) -> Result<Self> {
@@ -208,14 +223,20 @@ impl State {
     }
 }
 
+/// 合法手 (自殺手、打ち歩詰めを除く) をランダムに指す。
 pub fn interact() -> Result<()> {
+    interact_with_policy(IllegalPolicy::Strict)
+}
+
+/// policy に従って your 側の指し手をランダムに指す。
+pub fn interact_with_policy(policy: IllegalPolicy) -> Result<()> {
     use std::io::{self, BufRead};
 
     let stdin = io::stdin();
     let stdin = stdin.lock();
     let rdr = io::BufReader::new(stdin);
 
-    let mut state = State::new();
+    let mut state = State::new(policy);
     for line in rdr.lines() {
         let line = line?;
         let mut it = line.split_ascii_whitespace();
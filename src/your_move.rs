@@ -15,12 +15,12 @@ use either::Either;
 
 use crate::ai;
 use crate::effect;
-use crate::position::PawnMask;
+use crate::position;
 use crate::prelude::*;
 
 /// your 側の指し手の疑似合法性判定。
 /// 打ち歩詰め及び自殺手は許される。
-/// テスト用。思考ルーチンでは使われない。
+/// テストや棋譜の再生時の検証用。思考ルーチンでは使われない。
 ///
 /// src != dst などの条件は Move 生成時にチェック済み。
 pub fn is_pseudo_legal(pos: &Position, mv: &Move) -> bool {
@@ -194,33 +194,25 @@ fn is_pseudo_legal_nondrop_ranged_dir(
 
 /// drop の疑似合法性判定。
 fn is_pseudo_legal_drop(pos: &Position, drop: &MoveDrop) -> bool {
-    let your = pos.side();
-    let pt = drop.pt;
-    let dst = drop.dst;
-
-    if !pos.board()[dst].is_empty() {
-        return false;
-    }
-
-    // 持ってない駒を打つのはNG
-    if pos.hand(your)[pt] == 0 {
-        return false;
-    }
+    can_drop(pos, drop.pt, drop.dst)
+}
 
-    // 行きどころのない駒はNG
-    if !dst.can_put(your, pt) {
-        return false;
-    }
+/// your 側が持駒 pt を dst に打てるかどうかを返す。
+/// 原作では your 側の打ち歩詰めが許されるので、打ち歩詰めも可とする。
+pub fn can_drop(pos: &Position, pt: Piece, dst: Sq) -> bool {
+    position::can_drop(pos, pt, dst, true)
+}
 
-    // 二歩チェック
-    if matches!(pt, Piece::Pawn) {
-        let pawn_mask = PawnMask::from_board_side(pos.board(), your);
-        if pawn_mask.test(dst.x().get()) {
-            return false;
-        }
-    }
+/// 手番側が持駒 pt を dst に打つと王手になるかどうかを返す。
+/// 局面を変更せず、dst に置いた pt の利きが相手玉に届くかだけを調べる。
+/// 打つ手が合法かどうかは調べない。
+///
+/// 駒打ちで開き王手は生じないので、これで王手判定として十分。
+pub fn drop_gives_check(pos: &Position, pt: Piece, dst: Sq) -> bool {
+    let side = pos.side();
+    let sq_king = unwrap_or_return!(ai::find_king_sq(pos.board(), side.inv()), false);
 
-    true
+    effect::iter_effects_by(pos.board(), side, dst, pt).any(|sq| sq == sq_king)
 }
 
 /// your 側の王手回避手を列挙する。
@@ -368,57 +360,87 @@ fn moves_evasion_nondrop_dragon(pos: &Position, src: Sq) -> impl Iterator<Item =
 /// your 側の drop 王手回避手を列挙する。
 /// 玉周り最大 9 マスしか調べないので、入玉形だと詰み判定を誤るケースがありうる。
 fn moves_evasion_drop(pos: &Position, sq_king_your: Sq) -> impl Iterator<Item = Move> + '_ {
-    const NEIGHBOR: &[i32] = &[-12, -11, -10, -1, 0, 1, 10, 11, 12];
-
     let your = pos.side();
-    let pawn_mask = PawnMask::from_board_side(pos.board(), your);
+    let pawn_mask = pos.pawn_mask(your);
 
     let is_ok = move |drop: &MoveDrop| -> bool {
-        let pt = drop.pt;
-        let dst = drop.dst;
+        position::can_drop_on_board(pos.board(), your, pawn_mask, drop.pt, drop.dst)
+    };
 
-        // 移動先が空白でないならNG
-        if !pos.board()[dst].is_empty() {
-            return false;
-        }
+    // 玉のマス自体は空白でないので調べなくてよい
+    sq_king_your
+        .neighbors()
+        .flat_map(move |dst| {
+            let pts = Piece::iter_hand().filter(move |&pt| pos.hand(your)[pt] > 0);
+            pts.map(move |pt| MoveDrop::new(pt, dst))
+        })
+        .filter(is_ok)
+        .map(Move::Drop)
+}
 
-        // 行きどころのない駒はNG
-        if !dst.can_put(your, pt) {
-            return false;
-        }
+/// your 側の指し手の制限方針。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IllegalPolicy {
+    Faithful, // 原作通り、自殺手と打ち歩詰めを許す (moves_pseudo_legal)
+    Strict,   // 自殺手と打ち歩詰めを許さない (本来の将棋のルール)
+}
 
-        // 二歩はNG
-        if matches!(pt, Piece::Pawn) && pawn_mask.test(dst.x().get()) {
-            return false;
+/// policy に従って your 側の指し手を列挙する。
+/// テスト用。思考ルーチンでは使われない。
+pub fn moves_with_policy(pos: &mut Position, policy: IllegalPolicy) -> Vec<Move> {
+    match policy {
+        IllegalPolicy::Faithful => moves_pseudo_legal(pos).collect(),
+        IllegalPolicy::Strict => {
+            let mvs: Vec<_> = moves_legal(pos).collect();
+            mvs.into_iter()
+                .filter(|mv| match mv {
+                    Move::Drop(drop) if drop.pt == Piece::Pawn => {
+                        position::can_drop(pos, drop.pt, drop.dst, false)
+                    }
+                    _ => true,
+                })
+                .collect()
         }
-
-        true
-    };
-
-    let sqs = NEIGHBOR.iter().filter_map(move |di| {
-        let sq = sq_king_your + *di;
-        sq.is_valid().as_some(sq)
-    });
-
-    sqs.flat_map(move |dst| {
-        let pts = Piece::iter_hand().filter(move |&pt| pos.hand(your)[pt] > 0);
-        pts.map(move |pt| MoveDrop::new(pt, dst))
-    })
-    .filter(is_ok)
-    .map(Move::Drop)
+    }
 }
 
 /// your 側の合法手を列挙する。
 /// 打ち歩詰めは含まれるが、自殺手は含まれない。
 /// テスト用。思考ルーチンでは使われない。
+///
+/// 自殺手の判定のために全ての手を実際に指してみるのは遅いので、王手が掛かっていなければ
+/// 以下のように判定を省く:
+///
+///   * 駒打ちは常に合法。
+///   * 玉の移動は position::king_escape_squares() で判定する。
+///   * 玉と縦横斜めに並んでいない駒の移動は常に合法 (ピンされえない)。
+///
+/// 残りの手 (ピンされている可能性がある駒の移動) のみ実際に指して調べる。
 pub fn moves_legal(pos: &mut Position) -> impl Iterator<Item = Move> {
+    let your = pos.side();
     let mut mvs: Vec<_> = moves_pseudo_legal(pos).collect();
 
-    mvs.retain(|mv| {
-        let cmd = pos.do_move(mv).unwrap();
-        let ok = !pos.can_capture_king();
-        pos.undo_move(&cmd).unwrap();
-        ok
+    let sq_king = match ai::find_king_sq(pos.board(), your) {
+        Some(sq) => sq,
+        None => return mvs.into_iter(),
+    };
+    if pos.in_check(your) {
+        mvs.retain(|mv| position::with_move(pos, mv, |pos| !pos.can_capture_king()).unwrap());
+        return mvs.into_iter();
+    }
+
+    let sqs_escape = position::king_escape_squares(pos, your);
+    let is_aligned = |sq: Sq| {
+        let dx = sq.x().get() - sq_king.x().get();
+        let dy = sq.y().get() - sq_king.y().get();
+        dx == 0 || dy == 0 || dx.abs() == dy.abs()
+    };
+
+    mvs.retain(|mv| match mv {
+        Move::Drop(_) => true,
+        Move::Nondrop(nondrop) if nondrop.src == sq_king => sqs_escape.contains(&nondrop.dst),
+        Move::Nondrop(nondrop) if !is_aligned(nondrop.src) => true,
+        _ => position::with_move(pos, mv, |pos| !pos.can_capture_king()).unwrap(),
     });
 
     mvs.into_iter()
@@ -435,63 +457,66 @@ pub fn moves_pseudo_legal(pos: &Position) -> impl Iterator<Item = Move> + '_ {
     )
 }
 
-fn moves_pseudo_legal_nondrop(pos: &Position) -> impl Iterator<Item = Move> + '_ {
-    let your = pos.side();
+/// your 側の src にある駒を動かす疑似合法手を列挙する。
+/// src に your 駒がなければ空となる。
+/// 自殺手が含まれる。
+pub fn moves_from(pos: &Position, src: Sq) -> Vec<Move> {
+    moves_illegal_nondrop_from(pos, src)
+        .filter(|nondrop| is_pseudo_legal_nondrop_by_effect(pos, nondrop))
+        .map(Move::Nondrop)
+        .collect()
+}
 
-    let is_ok = move |nondrop: &MoveNondrop| -> bool {
-        let src = nondrop.src;
-        let dst = nondrop.dst;
-        let is_promotion = nondrop.is_promotion;
+/// your 側の持駒 pt を打つ疑似合法手を列挙する。
+/// 打ち歩詰めと自殺手が含まれる。
+pub fn moves_drop(pos: &Position, pt: Piece) -> Vec<Move> {
+    moves_pseudo_legal_drop(pos)
+        .filter(|mv| mv.is_drop_pt(pt))
+        .collect()
+}
 
-        // 移動先が your 駒ならNG
-        if pos.board()[dst].is_side(your) {
-            return false;
-        }
+fn moves_pseudo_legal_nondrop(pos: &Position) -> impl Iterator<Item = Move> + '_ {
+    moves_illegal_nondrop(pos)
+        .filter(move |nondrop| is_pseudo_legal_nondrop_by_effect(pos, nondrop))
+        .map(Move::Nondrop)
+}
 
-        // 成り処理
-        let mut pt = pos.board()[src].piece_of(your).unwrap();
-        if is_promotion {
-            if !can_promote(your, pt, src, dst) {
-                return false;
-            }
-            pt = pt.to_promoted().unwrap();
-        }
+/// 駒の利きから生成した MoveNondrop の疑似合法性判定。
+/// src に your 駒があり、dst がその駒の利きの範囲内であることは仮定している。
+fn is_pseudo_legal_nondrop_by_effect(pos: &Position, nondrop: &MoveNondrop) -> bool {
+    let your = pos.side();
+    let src = nondrop.src;
+    let dst = nondrop.dst;
+    let is_promotion = nondrop.is_promotion;
+
+    // 移動先が your 駒ならNG
+    if pos.board()[dst].is_side(your) {
+        return false;
+    }
 
-        // 行きどころのない駒はNG
-        if !dst.can_put(your, pt) {
+    // 成り処理
+    let mut pt = pos.board()[src].piece_of(your).unwrap();
+    if is_promotion {
+        if !can_promote(your, pt, src, dst) {
             return false;
         }
+        pt = pt.to_promoted().unwrap();
+    }
 
-        true
-    };
+    // 行きどころのない駒はNG
+    if !dst.can_put(your, pt) {
+        return false;
+    }
 
-    moves_illegal_nondrop(pos).filter(is_ok).map(Move::Nondrop)
+    true
 }
 
 fn moves_pseudo_legal_drop(pos: &Position) -> impl Iterator<Item = Move> + '_ {
     let your = pos.side();
-    let pawn_mask = PawnMask::from_board_side(pos.board(), your);
+    let pawn_mask = pos.pawn_mask(your);
 
     let is_ok = move |drop: &MoveDrop| -> bool {
-        let pt = drop.pt;
-        let dst = drop.dst;
-
-        // 移動先が空白でないならNG
-        if !pos.board()[dst].is_empty() {
-            return false;
-        }
-
-        // 行きどころのない駒はNG
-        if !dst.can_put(your, pt) {
-            return false;
-        }
-
-        // 二歩はNG
-        if matches!(pt, Piece::Pawn) && pawn_mask.test(dst.x().get()) {
-            return false;
-        }
-
-        true
+        position::can_drop_on_board(pos.board(), your, pawn_mask, drop.pt, drop.dst)
     };
 
     moves_illegal_drop(pos).filter(is_ok).map(Move::Drop)
@@ -519,6 +544,22 @@ fn moves_illegal_nondrop(pos: &Position) -> impl Iterator<Item = MoveNondrop> +
         })
 }
 
+/// src にある your 駒による、違法手も含む MoveNondrop を列挙する。
+fn moves_illegal_nondrop_from(pos: &Position, src: Sq) -> impl Iterator<Item = MoveNondrop> + '_ {
+    let your = pos.side();
+
+    pos.board()[src]
+        .piece_of(your)
+        .into_iter()
+        .flat_map(move |pt| effect::iter_effects_by(pos.board(), your, src, pt))
+        .filter(|dst| dst.is_valid())
+        .flat_map(move |dst| {
+            [false, true]
+                .iter()
+                .map(move |is_promotion| MoveNondrop::new(src, dst, *is_promotion))
+        })
+}
+
 /// 違法手も含む Drop を列挙する。
 fn moves_illegal_drop(pos: &Position) -> impl Iterator<Item = MoveDrop> + '_ {
     let your = pos.side();
@@ -550,4 +591,65 @@ mod tests {
             assert_eq!(mvs_gen, mvs_filt);
         }
     }
+
+    #[test]
+    fn test_moves_legal() {
+        for _ in 0..300 {
+            let mut pos = Position::random(&mut rand::thread_rng());
+            // 玉を取る手は適用できない
+            if pos.can_capture_king() {
+                continue;
+            }
+            let mvs: HashSet<_> = moves_legal(&mut pos).collect();
+
+            // 全ての疑似合法手を実際に指して判定した結果と一致するか?
+            let mvs_naive: Vec<_> = moves_pseudo_legal(&pos).collect();
+            let mvs_naive: HashSet<_> = mvs_naive
+                .into_iter()
+                .filter(|mv| {
+                    position::with_move(&mut pos, mv, |pos| !pos.can_capture_king()).unwrap()
+                })
+                .collect();
+            assert_eq!(mvs, mvs_naive);
+        }
+    }
+
+    #[test]
+    fn test_drop_gives_check() {
+        for _ in 0..100 {
+            let mut pos = Position::random(&mut rand::thread_rng());
+            // 既に王手が掛かっている局面では比較できない
+            if pos.can_capture_king() {
+                continue;
+            }
+            let side = pos.side();
+            let mvs: Vec<_> = moves_pseudo_legal(&pos).filter(Move::is_drop).collect();
+            for mv in mvs {
+                let (pt, dst) = match &mv {
+                    Move::Drop(drop) => (drop.pt, drop.dst),
+                    _ => unreachable!(),
+                };
+                let expect =
+                    position::with_move(&mut pos, &mv, |pos| pos.in_check(side.inv())).unwrap();
+                assert_eq!(drop_gives_check(&pos, pt, dst), expect);
+            }
+        }
+    }
+
+    #[test]
+    fn test_moves_from() {
+        for _ in 0..100 {
+            let pos = Position::random(&mut rand::thread_rng());
+            let your = pos.side();
+            let mvs_gen: HashSet<_> = moves_pseudo_legal(&pos).collect();
+
+            // マスごと/駒種ごとの列挙を合わせると全体の列挙と一致するか?
+            let mvs_nondrop = Sq::iter_valid().flat_map(|src| moves_from(&pos, src));
+            let mvs_drop = Piece::iter_hand()
+                .filter(|&pt| pos.hand(your)[pt] > 0)
+                .flat_map(|pt| moves_drop(&pos, pt));
+            let mvs_split: HashSet<_> = mvs_nondrop.chain(mvs_drop).collect();
+            assert_eq!(mvs_gen, mvs_split);
+        }
+    }
 }
@@ -2,11 +2,17 @@
 //! your 側プレイヤー
 //!===================================================================
 
-use rand::seq::IteratorRandom;
+use rand::rngs::StdRng;
+use rand::seq::{IteratorRandom, SliceRandom};
+use rand::SeedableRng;
 
+use crate::ai;
+use crate::effect::EffectBoard;
+use crate::position;
 use crate::prelude::*;
+use crate::price::PRICES_0;
 use crate::record::{Record, RecordEntry};
-use crate::your_move;
+use crate::your_move::{self, IllegalPolicy};
 
 pub trait YourPlayer {
     /// 実装の都合上 pos は &mut にしているが、内容を変更したら元に戻すこと。
@@ -47,6 +53,119 @@ impl YourPlayer for YourPlayerPseudoLegal {
     }
 }
 
+/// policy に従う手をランダムに指す。
+#[derive(Debug)]
+pub struct YourPlayerRandom {
+    policy: IllegalPolicy,
+    rng: StdRng,
+}
+
+impl YourPlayerRandom {
+    pub fn new(policy: IllegalPolicy) -> Self {
+        Self {
+            policy,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// 乱数シードを固定する。同じシードなら同じ局面で同じ手を指す。
+    pub fn with_seed(policy: IllegalPolicy, seed: u64) -> Self {
+        Self {
+            policy,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl YourPlayer for YourPlayerRandom {
+    fn think(&mut self, pos: &mut Position) -> Option<Move> {
+        your_move::moves_with_policy(pos, self.policy)
+            .choose(&mut self.rng)
+            .cloned()
+    }
+}
+
+/// 自殺手を除いた手のうち、最も価値の高い駒を取る手を指す。
+/// 取れる駒がなければランダムに指す。同価値の手が複数あればその中からランダムに選ぶ。
+#[derive(Debug, Default)]
+pub struct YourPlayerGreedy;
+
+impl YourPlayerGreedy {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl YourPlayer for YourPlayerGreedy {
+    fn think(&mut self, pos: &mut Position) -> Option<Move> {
+        let mut rng = rand::thread_rng();
+        let your = pos.side();
+
+        let mvs: Vec<_> = your_move::moves_legal(pos).collect();
+
+        let capture_price = |mv: &Move| {
+            pos.board()[mv.dst()]
+                .piece_of(your.inv())
+                .map_or(0, |pt| PRICES_0[pt])
+        };
+        let price_max = mvs.iter().map(capture_price).max()?;
+        let mvs: Vec<_> = mvs
+            .into_iter()
+            .filter(|mv| capture_price(mv) == price_max)
+            .collect();
+
+        mvs.choose(&mut rng).cloned()
+    }
+}
+
+/// 自殺手を除いた手のうち、自玉の安全度が最大となる手を指す。
+/// 安全度は自玉から距離 2 以下のマスへの (自駒の利き数) - (敵駒の利き数) の総和。
+/// 同じ安全度の手が複数あればその中からランダムに選ぶ。
+#[derive(Debug, Default)]
+pub struct YourPlayerDefensive;
+
+impl YourPlayerDefensive {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn king_safety(pos: &Position, side: Side) -> i32 {
+        let sq_king = unwrap_or_return!(ai::find_king_sq(pos.board(), side), i32::MIN);
+        let eff_board = EffectBoard::from_board(pos.board(), side);
+
+        Sq::iter_valid()
+            .filter(|&sq| Sq::dist(sq, sq_king).unwrap() <= 2)
+            .map(|sq| {
+                i32::from(eff_board[sq][side].count())
+                    - i32::from(eff_board[sq][side.inv()].count())
+            })
+            .sum()
+    }
+}
+
+impl YourPlayer for YourPlayerDefensive {
+    fn think(&mut self, pos: &mut Position) -> Option<Move> {
+        let mut rng = rand::thread_rng();
+        let your = pos.side();
+
+        let mvs: Vec<_> = your_move::moves_legal(pos)
+            .map(|mv| {
+                let safety =
+                    position::with_move(pos, &mv, |pos| Self::king_safety(pos, your)).unwrap();
+                (mv, safety)
+            })
+            .collect();
+        let safety_max = mvs.iter().map(|(_, safety)| *safety).max()?;
+        let mvs: Vec<_> = mvs
+            .into_iter()
+            .filter(|(_, safety)| *safety == safety_max)
+            .map(|(mv, _)| mv)
+            .collect();
+
+        mvs.choose(&mut rng).cloned()
+    }
+}
+
 /// 棋譜再現プレイヤー。
 #[derive(Debug)]
 pub struct YourPlayerRecord {